@@ -0,0 +1,39 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::helpers::time_formatter::parse_cli_date;
+use crate::models::dataset::Dataset;
+
+#[derive(Parser)]
+#[command(name = "crypto-api", about = "Midgard history API and ingester")]
+pub struct Cli {
+    /// Configuration file, defaults to `CONFIG_PATH` or `config.toml`
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the HTTP API together with the hourly ingestion scheduler (default)
+    Serve,
+    /// Fetch a historical range from Midgard, resuming from its last checkpoint
+    Backfill {
+        /// depths, earnings, members (runepool) or swaps
+        #[arg(long)]
+        collection: Dataset,
+        /// Start of the range, `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS` (UTC)
+        #[arg(long, value_parser = parse_cli_date)]
+        from: i64,
+        /// End of the range. Defaults to the end of the interrupted run being resumed, or else
+        /// to the start of the current hour
+        #[arg(long, value_parser = parse_cli_date)]
+        to: Option<i64>,
+        /// Pool to backfill depths for, defaults to BTC.BTC
+        #[arg(long)]
+        pool: Option<String>,
+    },
+    /// Fill the gaps of every collection over the lookback window once and exit
+    SyncOnce,
+}
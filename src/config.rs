@@ -0,0 +1,545 @@
+use dotenv::dotenv;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::{env, fs};
+use tracing_subscriber::EnvFilter;
+
+use crate::helpers::cron::parse_cron;
+use crate::midgard::client::DEFAULT_BASE_URL;
+use crate::models::dataset::Dataset;
+
+// File read when neither `--config` nor `CONFIG_PATH` names one; it may be missing
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+#[derive(Debug)]
+pub struct ConfigError(pub String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration: {}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub midgard: MidgardConfig,
+    pub scheduler: SchedulerConfig,
+    pub api: ApiConfig,
+    pub health: HealthConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    // How long in-flight requests and ingestion get to finish once shutdown starts
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: String::from("0.0.0.0"),
+            port: 3000,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    // MongoDB connection string, required
+    pub url: String,
+    pub name: String,
+    pub collections: CollectionsConfig,
+    // Connection attempts at startup, waiting `connect_backoff_ms` after the first failure
+    // and twice as long after each one after that
+    pub connect_attempts: u32,
+    pub connect_backoff_ms: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            name: String::from("masterdb"),
+            collections: CollectionsConfig::default(),
+            connect_attempts: 5,
+            connect_backoff_ms: 1_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectionsConfig {
+    pub depths: String,
+    pub earnings: String,
+    pub members: String,
+    pub swaps: String,
+    pub sync_checkpoints: String,
+    pub sync_jobs: String,
+}
+
+impl Default for CollectionsConfig {
+    fn default() -> Self {
+        Self {
+            depths: Dataset::Depths.collection_name().to_string(),
+            earnings: Dataset::Earnings.collection_name().to_string(),
+            members: Dataset::Members.collection_name().to_string(),
+            swaps: Dataset::Swaps.collection_name().to_string(),
+            sync_checkpoints: String::from("sync_checkpoints"),
+            sync_jobs: String::from("sync_jobs"),
+        }
+    }
+}
+
+impl CollectionsConfig {
+    pub fn history(&self, dataset: Dataset) -> &str {
+        match dataset {
+            Dataset::Depths => &self.depths,
+            Dataset::Earnings => &self.earnings,
+            Dataset::Members => &self.members,
+            Dataset::Swaps => &self.swaps,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MidgardConfig {
+    pub url: String,
+    // Retries after the first attempt of a request
+    pub max_retries: u32,
+    pub base_delay_ms: u64,
+    // Longest wait between two attempts, also when `Retry-After` asks for more
+    pub max_delay_ms: u64,
+    // Minimum spacing between two requests to the same host
+    pub min_request_interval_ms: u64,
+}
+
+impl Default for MidgardConfig {
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_BASE_URL.to_string(),
+            max_retries: 5,
+            base_delay_ms: 500,
+            max_delay_ms: 60_000,
+            min_request_interval_ms: 3_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    // How far back each run looks for missing hourly buckets when nothing older is stored;
+    // otherwise runs look back to the oldest stored interval
+    pub lookback_hours: i64,
+    pub jobs: JobsConfig,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            lookback_hours: 24 * 7,
+            jobs: JobsConfig::default(),
+        }
+    }
+}
+
+// One ingestion job per dataset. The defaults run each one hourly, a few minutes apart, so
+// they don't queue behind each other on the Midgard rate limit.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobsConfig {
+    pub depths: JobConfig,
+    pub earnings: JobConfig,
+    pub members: JobConfig,
+    pub swaps: JobConfig,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self {
+            depths: JobConfig::hourly_at(5),
+            earnings: JobConfig::hourly_at(10),
+            members: JobConfig::hourly_at(15),
+            swaps: JobConfig::hourly_at(20),
+        }
+    }
+}
+
+impl JobsConfig {
+    pub fn get(&self, dataset: Dataset) -> &JobConfig {
+        match dataset {
+            Dataset::Depths => &self.depths,
+            Dataset::Earnings => &self.earnings,
+            Dataset::Members => &self.members,
+            Dataset::Swaps => &self.swaps,
+        }
+    }
+
+    fn get_mut(&mut self, dataset: Dataset) -> &mut JobConfig {
+        match dataset {
+            Dataset::Depths => &mut self.depths,
+            Dataset::Earnings => &mut self.earnings,
+            Dataset::Members => &mut self.members,
+            Dataset::Swaps => &mut self.swaps,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobConfig {
+    pub enabled: bool,
+    // Cron expression with a seconds field, e.g. `0 5 * * * *` for five past every hour
+    pub cron: String,
+    // A run still going after this long is abandoned and recorded as failed
+    pub timeout_secs: u64,
+}
+
+impl JobConfig {
+    fn hourly_at(minute: u32) -> Self {
+        Self {
+            enabled: true,
+            cron: format!("0 {} * * * *", minute),
+            timeout_secs: 1800,
+        }
+    }
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self::hourly_at(0)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    // Start of the range when a request has no `from`, April 1, 2022
+    pub default_from: i64,
+    pub max_count: MaxCountConfig,
+    // Bearer token required by the /admin endpoints, which are disabled without one
+    pub admin_token: Option<String>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            default_from: 1648771200,
+            max_count: MaxCountConfig::default(),
+            admin_token: None,
+        }
+    }
+}
+
+// Largest `count` each endpoint accepts for JSON responses
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaxCountConfig {
+    pub candles: i64,
+    pub depths: i64,
+    pub earnings: i64,
+    pub runepool: i64,
+    pub swaps: i64,
+}
+
+impl Default for MaxCountConfig {
+    fn default() -> Self {
+        Self {
+            candles: 400,
+            depths: 400,
+            earnings: 100,
+            runepool: 400,
+            swaps: 400,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    // `/readyz` fails once the newest interval of a collection is older than this.
+    // Ingestion runs hourly, so the newest complete hour is normally one to two hours old.
+    pub max_age_hours: i64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self { max_age_hours: 6 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // One JSON object per line, with the fields of the enclosing spans
+    Json,
+    // Human readable lines for local runs
+    Text,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "json" => Ok(LogFormat::Json),
+            "text" => Ok(LogFormat::Text),
+            _ => Err(format!(
+                "Unknown log format '{}', expected json or text",
+                value
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    // Filter in the `RUST_LOG` syntax, e.g. `info` or `info,crypto_api::db=debug`
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: String::from("info"),
+            format: LogFormat::Json,
+        }
+    }
+}
+
+impl Config {
+    // Reads `path`, `CONFIG_PATH` or `config.toml`, applies the environment overrides and
+    // validates the result. Only the default file may be missing.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        dotenv().ok();
+        let explicit = path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var("CONFIG_PATH").ok().map(Into::into));
+        let config = match &explicit {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+        let config = config.with_overrides(|key| env::var(key).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| ConfigError(format!("unable to read {}: {}", path.display(), e)))?;
+        Self::parse(&text).map_err(|e| ConfigError(format!("{}: {}", path.display(), e.0)))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        toml::from_str(text).map_err(|e| ConfigError(e.to_string()))
+    }
+
+    // Replaces every setting that `lookup` has a value for, keyed by environment variable.
+    // The names are listed in `config.example.toml`.
+    pub fn with_overrides(
+        mut self,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        fn set<T: FromStr>(
+            lookup: &impl Fn(&str) -> Option<String>,
+            key: &str,
+            target: &mut T,
+        ) -> Result<(), ConfigError> {
+            if let Some(value) = lookup(key) {
+                *target = value.trim().parse().map_err(|_| {
+                    ConfigError(format!("{} has an invalid value '{}'", key, value))
+                })?;
+            }
+            Ok(())
+        }
+
+        set(&lookup, "SERVER_HOST", &mut self.server.host)?;
+        set(&lookup, "SERVER_PORT", &mut self.server.port)?;
+        set(
+            &lookup,
+            "SERVER_SHUTDOWN_TIMEOUT_SECS",
+            &mut self.server.shutdown_timeout_secs,
+        )?;
+        set(&lookup, "DATABASE_URL", &mut self.database.url)?;
+        set(&lookup, "DATABASE_NAME", &mut self.database.name)?;
+        set(
+            &lookup,
+            "DATABASE_CONNECT_ATTEMPTS",
+            &mut self.database.connect_attempts,
+        )?;
+        set(
+            &lookup,
+            "DATABASE_CONNECT_BACKOFF_MS",
+            &mut self.database.connect_backoff_ms,
+        )?;
+        set(&lookup, "MIDGARD_URL", &mut self.midgard.url)?;
+        set(
+            &lookup,
+            "MIDGARD_MAX_RETRIES",
+            &mut self.midgard.max_retries,
+        )?;
+        set(
+            &lookup,
+            "MIDGARD_BASE_DELAY_MS",
+            &mut self.midgard.base_delay_ms,
+        )?;
+        set(
+            &lookup,
+            "MIDGARD_MAX_DELAY_MS",
+            &mut self.midgard.max_delay_ms,
+        )?;
+        set(
+            &lookup,
+            "MIDGARD_MIN_REQUEST_INTERVAL_MS",
+            &mut self.midgard.min_request_interval_ms,
+        )?;
+        set(
+            &lookup,
+            "BACKFILL_LOOKBACK_HOURS",
+            &mut self.scheduler.lookback_hours,
+        )?;
+        for dataset in Dataset::ALL {
+            let job = self.scheduler.jobs.get_mut(dataset);
+            let prefix = format!("SYNC_{}", dataset.name().to_uppercase());
+            set(&lookup, &format!("{}_ENABLED", prefix), &mut job.enabled)?;
+            set(&lookup, &format!("{}_CRON", prefix), &mut job.cron)?;
+            set(
+                &lookup,
+                &format!("{}_TIMEOUT_SECS", prefix),
+                &mut job.timeout_secs,
+            )?;
+        }
+        set(&lookup, "API_DEFAULT_FROM", &mut self.api.default_from)?;
+        if let Some(token) = lookup("API_ADMIN_TOKEN") {
+            self.api.admin_token = Some(token.trim().to_string());
+        }
+        set(
+            &lookup,
+            "READINESS_MAX_AGE_HOURS",
+            &mut self.health.max_age_hours,
+        )?;
+        set(&lookup, "LOG_LEVEL", &mut self.logging.level)?;
+        set(&lookup, "LOG_FORMAT", &mut self.logging.format)?;
+        Ok(self)
+    }
+
+    // Checks every setting, reporting all the problems at once
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        if self.server.host.trim().is_empty() {
+            problems.push(String::from("server.host must not be empty"));
+        }
+        if self.database.url.trim().is_empty() {
+            problems.push(String::from(
+                "database.url must be set, or DATABASE_URL in the environment",
+            ));
+        }
+        if self.database.name.trim().is_empty() {
+            problems.push(String::from("database.name must not be empty"));
+        }
+        if self.database.connect_attempts < 1 {
+            problems.push(String::from("database.connect_attempts must be at least 1"));
+        }
+
+        let collections = &self.database.collections;
+        let mut names: Vec<&str> = Dataset::ALL
+            .iter()
+            .map(|dataset| collections.history(*dataset))
+            .collect();
+        names.push(&collections.sync_checkpoints);
+        names.push(&collections.sync_jobs);
+        if names.iter().any(|name| name.trim().is_empty()) {
+            problems.push(String::from("database.collections must not be empty"));
+        }
+        names.sort();
+        if names.windows(2).any(|pair| pair[0] == pair[1]) {
+            problems.push(String::from("database.collections must all be different"));
+        }
+
+        if reqwest::Url::parse(&self.midgard.url).is_err() {
+            problems.push(format!(
+                "midgard.url '{}' is not a valid URL",
+                self.midgard.url
+            ));
+        }
+        if self.midgard.base_delay_ms > self.midgard.max_delay_ms {
+            problems.push(String::from(
+                "midgard.base_delay_ms must not exceed midgard.max_delay_ms",
+            ));
+        }
+        if self.scheduler.lookback_hours < 1 {
+            problems.push(String::from("scheduler.lookback_hours must be at least 1"));
+        }
+        for dataset in Dataset::ALL {
+            let job = self.scheduler.jobs.get(dataset);
+            if let Err(e) = parse_cron(&job.cron) {
+                problems.push(format!(
+                    "scheduler.jobs.{}.cron '{}' is not a valid cron expression: {}",
+                    dataset, job.cron, e
+                ));
+            }
+            if job.timeout_secs == 0 {
+                problems.push(format!(
+                    "scheduler.jobs.{}.timeout_secs must be at least 1",
+                    dataset
+                ));
+            }
+        }
+        if self
+            .api
+            .admin_token
+            .as_ref()
+            .is_some_and(|token| token.is_empty())
+        {
+            problems.push(String::from("api.admin_token must not be empty when set"));
+        }
+        if self.health.max_age_hours < 1 {
+            problems.push(String::from("health.max_age_hours must be at least 1"));
+        }
+        if EnvFilter::try_new(&self.logging.level).is_err() {
+            problems.push(format!(
+                "logging.level '{}' is not a valid filter",
+                self.logging.level
+            ));
+        }
+
+        let max_count = &self.api.max_count;
+        for (endpoint, count) in [
+            ("candles", max_count.candles),
+            ("depths", max_count.depths),
+            ("earnings", max_count.earnings),
+            ("runepool", max_count.runepool),
+            ("swaps", max_count.swaps),
+        ] {
+            if count < 1 {
+                problems.push(format!("api.max_count.{} must be at least 1", endpoint));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError(problems.join("; ")))
+        }
+    }
+}
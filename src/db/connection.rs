@@ -1,37 +1,611 @@
-use dotenv::dotenv;
-use mongodb::{error::Error, Client, Collection};
-use std::env;
+use async_trait::async_trait;
+use futures_util::{future, StreamExt, TryStreamExt};
+use mongodb::{
+    bson::{doc, Bson, Document},
+    error::Error,
+    options::{AggregateOptions, FindOneOptions, FindOptions, IndexOptions, ReplaceOptions},
+    Client, Collection, Database, IndexModel,
+};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tracing::{info, info_span, warn, Instrument};
 
+use crate::config::{CollectionsConfig, DatabaseConfig};
+use crate::db::store::{
+    bson_to_f64, interval_key, Accumulator, BucketPage, BucketQuery, BucketStream, HistoryStore,
+    Rollup, StoreError, UpsertSummary,
+};
+use crate::helpers::cursor::Cursor;
+use crate::helpers::metrics::observe_aggregation;
 use crate::models::{
-    depth_history_model::DepthHistoryInterval, earning_history_model::EarningHistoryInterval,
-    rptmuh_model::RpmuHistoryInterval, swap_history_model::SwapHistoryInterval,
+    checkpoint_model::SyncCheckpoint, dataset::Dataset, depth_history_model::DEFAULT_POOL,
+    sync_job_model::SyncJob,
 };
 
 #[derive(Clone)]
 pub struct MongoDB {
-    pub depths_history: Collection<DepthHistoryInterval>,
-    pub members_history: Collection<RpmuHistoryInterval>,
-    pub swaps_history: Collection<SwapHistoryInterval>,
-    pub earnings_history: Collection<EarningHistoryInterval>,
+    pub db: Database,
+    pub sync_checkpoints: Collection<SyncCheckpoint>,
+    pub sync_jobs: Collection<SyncJob>,
+    collections: CollectionsConfig,
 }
+
 impl MongoDB {
-    pub async fn init() -> Result<Self, Error> {
-        dotenv().ok();
-        let mongo_uri: String = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let client: Client = Client::with_uri_str(mongo_uri)
+    pub async fn init(config: &DatabaseConfig) -> Result<Self, Error> {
+        let client: Client = Client::with_uri_str(&config.url).await?;
+        let db = client.database(&config.name);
+        let sync_checkpoints: Collection<SyncCheckpoint> =
+            db.collection(&config.collections.sync_checkpoints);
+        let sync_jobs: Collection<SyncJob> = db.collection(&config.collections.sync_jobs);
+        let mongo_db = MongoDB {
+            db,
+            sync_checkpoints,
+            sync_jobs,
+            collections: config.collections.clone(),
+        };
+        mongo_db.ensure_indexes().await?;
+        Ok(mongo_db)
+    }
+
+    // Runs an aggregation over the intervals of `dataset`, reading its results one by one
+    async fn aggregate(
+        &self,
+        dataset: Dataset,
+        pipeline: Vec<Document>,
+    ) -> Result<BucketStream, StoreError> {
+        let aggregate_options = AggregateOptions::builder().allow_disk_use(true).build();
+        let cursor = self
+            .history(dataset)
+            .aggregate(pipeline, aggregate_options)
             .await
-            .expect("Unable to connect with MongoDB");
-        let db = client.database("masterdb");
-        let depths_history: Collection<DepthHistoryInterval> = db.collection("depths_history");
-        let members_history: Collection<RpmuHistoryInterval> = db.collection("members_history");
-        let swaps_history: Collection<SwapHistoryInterval> = db.collection("swaps_history");
-        let earnings_history: Collection<EarningHistoryInterval> =
-            db.collection("earnings_history");
-        Ok(MongoDB {
-            depths_history,
-            members_history,
-            swaps_history,
-            earnings_history,
+            .map_err(|e| StoreError(format!("Error fetching data: {}", e)))?;
+        Ok(cursor
+            .map(|document| document.map_err(|e| StoreError(format!("Error fetching data: {}", e))))
+            .boxed())
+    }
+
+    // Connects like `init`, retrying with exponential backoff while the database is
+    // unreachable, as it often is for a moment when both are started together
+    pub async fn connect(config: &DatabaseConfig) -> Result<Self, Error> {
+        let mut attempt = 1;
+        loop {
+            match Self::init(config).await {
+                Ok(mongo_db) => return Ok(mongo_db),
+                Err(e) if attempt < config.connect_attempts => {
+                    let delay = Duration::from_millis(
+                        config
+                            .connect_backoff_ms
+                            .saturating_mul(1 << (attempt - 1).min(16)),
+                    );
+                    warn!(
+                        error = %e,
+                        delay_ms = delay.as_millis() as u64,
+                        attempt,
+                        max_attempts = config.connect_attempts,
+                        "Error connecting to Database, retrying"
+                    );
+                    sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn history(&self, dataset: Dataset) -> Collection<Document> {
+        self.db.collection(self.collections.history(dataset))
+    }
+
+    // Creates the unique interval indexes that make re-ingesting a window idempotent
+    pub async fn ensure_indexes(&self) -> Result<(), Error> {
+        // Depth intervals stored before documents carried a pool were all BTC.BTC
+        self.history(Dataset::Depths)
+            .update_many(
+                doc! { "pool": { "$exists": false } },
+                doc! { "$set": { "pool": DEFAULT_POOL } },
+                None,
+            )
+            .await?;
+
+        for dataset in Dataset::ALL {
+            let mut keys = Document::new();
+            for field in interval_key(dataset) {
+                keys.insert(field, 1);
+            }
+            let index = IndexModel::builder()
+                .keys(keys)
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .name(format!("{}_interval_unique", dataset))
+                        .build(),
+                )
+                .build();
+            let removed = self.remove_duplicate_intervals(dataset).await?;
+            if removed > 0 {
+                warn!(
+                    collection = self.collections.history(dataset),
+                    removed, "Removed duplicate intervals"
+                );
+            }
+            // Without the index re-ingesting a window is not idempotent, so this fails startup
+            self.history(dataset).create_index(index, None).await?;
+        }
+        Ok(())
+    }
+
+    // Keeps the newest document of every interval key stored more than once, as written
+    // before ingestion upserted, so the unique index can be built
+    async fn remove_duplicate_intervals(&self, dataset: Dataset) -> Result<u64, Error> {
+        let mut key = Document::new();
+        for field in interval_key(dataset) {
+            key.insert(field, format!("${}", field));
+        }
+        let pipeline = vec![
+            doc! { "$sort": { "_id": 1 } },
+            doc! { "$group": { "_id": key, "ids": { "$push": "$_id" }, "count": { "$sum": 1 } } },
+            doc! { "$match": { "count": { "$gt": 1 } } },
+        ];
+        let options = AggregateOptions::builder().allow_disk_use(true).build();
+        let mut duplicates = self.history(dataset).aggregate(pipeline, options).await?;
+
+        let mut removed = 0;
+        while let Some(duplicate) = duplicates.try_next().await? {
+            let mut ids = duplicate.get_array("ids").cloned().unwrap_or_default();
+            ids.pop();
+            removed += self
+                .history(dataset)
+                .delete_many(doc! { "_id": { "$in": ids } }, None)
+                .await?
+                .deleted_count;
+        }
+        Ok(removed)
+    }
+}
+
+// Buckets that sort strictly after the cursor position: equal on the keys before the
+// first one that differs, and past the cursor on that one
+fn seek_after(cursor: &Cursor) -> Document {
+    let mut branches = Vec::new();
+    let mut equal = Document::new();
+    for (key, value) in cursor.position() {
+        let operator = if key.order < 0 { "$lt" } else { "$gt" };
+        let mut branch = equal.clone();
+        branch.insert(key.field.as_str(), doc! { operator: value.clone() });
+        branches.push(branch);
+        equal.insert(key.field.as_str(), value.clone());
+    }
+    doc! { "$or": branches }
+}
+
+// Stages that group the intervals of `query` into buckets in the requested order
+fn bucket_stages(query: &BucketQuery) -> Vec<Document> {
+    // Only the fields the rollup reads are carried through the sort and group
+    let mut source = doc! { "_id": 0, "startTime": 1 };
+    for rollup in &query.rollup {
+        source.insert(rollup.field, 1);
+        if let Accumulator::WeightedAvg(weight) = rollup.accumulator {
+            source.insert(weight, 1);
+        }
+    }
+    let mut pipeline = vec![
+        doc! { "$match": query.filter.clone() },
+        doc! { "$project": source },
+    ];
+    if let Some(pool) = &query.pools_filter {
+        pipeline.push(doc! { "$addFields": {
+            "pools": {
+                "$filter": {
+                    "input": "$pools",
+                    "as": "pool",
+                    "cond": { "$eq": ["$$pool.pool", pool] }
+                }
+            }
+        }});
+    }
+
+    // Buckets follow the calendar of the requested timezone, with weeks starting on Monday
+    let bucket = doc! { "$dateTrunc": {
+        "date": { "$toDate": { "$multiply": ["$startTime", 1000] } },
+        "unit": query.bucketing.interval.name(),
+        "timezone": query.bucketing.timezone.name(),
+        "startOfWeek": "monday",
+    }};
+    let mut group = doc! { "_id": bucket };
+    // Weighted averages are grouped as two sums and divided afterwards
+    let mut weighted = doc! {};
+    let mut hidden = doc! { "_id": 0 };
+    for rollup in &query.rollup {
+        let field = format!("${}", rollup.field);
+        let accumulator = match rollup.accumulator {
+            Accumulator::First => doc! { "$first": field },
+            Accumulator::Last => doc! { "$last": field },
+            Accumulator::Sum => doc! { "$sum": field },
+            Accumulator::Avg => doc! { "$avg": field },
+            Accumulator::Min => doc! { "$min": field },
+            Accumulator::Max => doc! { "$max": field },
+            // Summed per pool by `pool_sum_stages`
+            Accumulator::SumByPool(_) => continue,
+            Accumulator::WeightedAvg(weight) => {
+                let weight_field = format!("{}__weight", rollup.name);
+                group.insert(
+                    weight_field.clone(),
+                    doc! { "$sum": format!("${}", weight) },
+                );
+                weighted.insert(
+                    rollup.name,
+                    doc! { "$cond": [
+                        { "$gt": [format!("${}", weight_field), 0] },
+                        { "$divide": [field.clone(), format!("${}", weight_field)] },
+                        0.0
+                    ]},
+                );
+                hidden.insert(weight_field, 0);
+                doc! { "$sum": { "$multiply": [field, format!("${}", weight)] } }
+            }
+        };
+        group.insert(rollup.name, accumulator);
+    }
+
+    pipeline.push(doc! { "$sort": { "startTime": 1 } });
+    let by_pool = query
+        .rollup
+        .iter()
+        .find_map(|rollup| match rollup.accumulator {
+            Accumulator::SumByPool(fields) => Some((rollup, fields)),
+            _ => None,
+        });
+    match by_pool {
+        Some((rollup, fields)) => pipeline.extend(pool_sum_stages(rollup, fields, group)),
+        None => pipeline.push(doc! { "$group": group }),
+    }
+    if !weighted.is_empty() {
+        pipeline.push(doc! { "$addFields": weighted });
+    }
+    if !query.conditions.is_empty() {
+        pipeline.push(doc! { "$match": query.conditions.clone() });
+    }
+
+    // Sorted once the buckets are complete, so the order of the output is the requested one
+    let mut sort_doc = doc! {};
+    for key in &query.sort {
+        sort_doc.insert(key.field.as_str(), key.order);
+    }
+    pipeline.extend([doc! { "$project": hidden }, doc! { "$sort": sort_doc }]);
+    pipeline
+}
+
+// Groups the intervals with the entries of their `pools` array summed per pool, so buckets
+// hold one entry per pool rather than every hourly array. Each interval is unwound into a
+// row of its own, with no pool, and one row per pool entry. The first `$group`, keyed on
+// bucket and pool, sums the pool fields and computes `group` over the pool-less rows of a
+// bucket as if there were no pools. The second one keeps those and pushes the summed pools
+// in the order they were first seen.
+fn pool_sum_stages(rollup: &Rollup, fields: &[&str], group: Document) -> Vec<Document> {
+    let array = format!("${}", rollup.field);
+    let mut regroup = doc! { "_id": "$_id.bucket" };
+    for name in group.keys().filter(|name| *name != "_id") {
+        regroup.insert(name, doc! { "$first": format!("${}", name) });
+    }
+
+    let mut by_pool = group;
+    let bucket = by_pool.remove("_id").unwrap_or_default();
+    by_pool.insert(
+        "_id",
+        doc! { "bucket": bucket, "pool": { "$ifNull": [format!("{}.pool", array), Bson::Null] } },
+    );
+    // Intervals come in `startTime` order, each one's own row before its pools
+    by_pool.insert(
+        "__order",
+        doc! { "$min": { "$add": [{ "$multiply": ["$startTime", 1000] }, "$__index"] } },
+    );
+    let mut pool = doc! { "pool": "$_id.pool" };
+    for field in fields {
+        let sum = format!("__pool_{}", field);
+        by_pool.insert(&sum, doc! { "$sum": format!("{}.{}", array, field) });
+        pool.insert(*field, format!("${}", sum));
+    }
+    regroup.insert(
+        rollup.name,
+        doc! { "$push": { "$cond": [{ "$eq": ["$_id.pool", Bson::Null] }, "$$REMOVE", pool] } },
+    );
+
+    vec![
+        doc! { "$addFields": {
+            rollup.field: { "$concatArrays": [[Bson::Null], { "$ifNull": [&array, []] }] }
+        }},
+        doc! { "$unwind": { "path": &array, "includeArrayIndex": "__index" } },
+        doc! { "$group": by_pool },
+        doc! { "$sort": { "__order": 1 } },
+        doc! { "$group": regroup },
+    ]
+}
+
+// Translates a bucket query into an aggregation pipeline returning its page
+pub fn bucket_pipeline(query: &BucketQuery) -> Vec<Document> {
+    let mut pipeline = bucket_stages(query);
+    if let Some(cursor) = &query.after {
+        pipeline.push(doc! { "$match": seek_after(cursor) });
+    }
+    pipeline.push(doc! { "$skip": query.skip });
+    pipeline.push(doc! { "$limit": query.limit });
+    pipeline
+}
+
+// Counts the buckets of `query`, in all and from the start of its page on, in a separate
+// aggregation so the page isn't held in one document with them. Only the sort keys and
+// the fields of the conditions are rolled up, which is all the order and the matches need.
+pub fn count_pipeline(query: &BucketQuery) -> Vec<Document> {
+    let counted = BucketQuery {
+        pools_filter: None,
+        rollup: query
+            .rollup
+            .iter()
+            .filter(|rollup| {
+                query.sort.iter().any(|key| key.field == rollup.name)
+                    || query.conditions.contains_key(rollup.name)
+            })
+            .cloned()
+            .collect(),
+        ..query.clone()
+    };
+    let mut pipeline = bucket_stages(&counted);
+    let mut remaining = Vec::new();
+    if let Some(cursor) = &query.after {
+        remaining.push(doc! { "$match": seek_after(cursor) });
+    }
+    remaining.push(doc! { "$skip": query.skip });
+    remaining.push(doc! { "$count": "count" });
+    pipeline.push(doc! { "$facet": {
+        "total": [{ "$count": "count" }],
+        "remaining": remaining,
+    }});
+    pipeline
+}
+
+// Reads a `$count` facet, which is empty when nothing matched
+fn facet_count(facets: &Document, name: &str) -> u64 {
+    facets
+        .get_array(name)
+        .ok()
+        .and_then(|counts| counts.first())
+        .and_then(Bson::as_document)
+        .and_then(|count| count.get("count"))
+        .and_then(bson_to_f64)
+        .unwrap_or_default() as u64
+}
+
+#[async_trait]
+impl HistoryStore for MongoDB {
+    // Upserts in a single bulk `update` command
+    async fn upsert(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+        intervals: Vec<Document>,
+    ) -> Result<UpsertSummary, StoreError> {
+        if intervals.is_empty() {
+            return Ok(UpsertSummary::default());
+        }
+
+        let mut updates = Vec::with_capacity(intervals.len());
+        for mut document in intervals {
+            document.extend(scope.clone());
+            let mut filter = scope.clone();
+            for field in interval_key(dataset) {
+                if let Some(value) = document.get(field) {
+                    filter.insert(field, value.clone());
+                }
+            }
+            updates.push(doc! { "q": filter, "u": { "$set": document }, "upsert": true });
+        }
+
+        let response = self
+            .db
+            .run_command(
+                doc! {
+                    "update": self.collections.history(dataset),
+                    "updates": updates,
+                    "ordered": false,
+                },
+                None,
+            )
+            .await
+            .map_err(|e| StoreError(format!("Error Upserting Data into DB: {:?}", e)))?;
+
+        if let Ok(errors) = response.get_array("writeErrors") {
+            return Err(StoreError(format!(
+                "Error Upserting Data into DB: {:?}",
+                errors
+            )));
+        }
+        let upserted = response
+            .get_array("upserted")
+            .map(|ids| ids.len() as u64)
+            .unwrap_or(0);
+        let total = response.get_i32("n").map(|n| n as u64).unwrap_or(upserted);
+        Ok(UpsertSummary {
+            matched: total - upserted,
+            upserted,
         })
     }
+
+    async fn start_times(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<i64>, StoreError> {
+        let mut filter = scope;
+        filter.insert("startTime", doc! { "$gte": from as f64, "$lt": to as f64 });
+        let values = self
+            .history(dataset)
+            .distinct("startTime", filter, None)
+            .await?;
+        Ok(values
+            .iter()
+            .filter_map(bson_to_f64)
+            .map(|start| start as i64)
+            .collect())
+    }
+
+    async fn depth_pools(&self) -> Result<Vec<String>, StoreError> {
+        let values = self
+            .history(Dataset::Depths)
+            .distinct("pool", None, None)
+            .await?;
+        Ok(values
+            .into_iter()
+            .filter_map(|pool| match pool {
+                Bson::String(pool) => Some(pool),
+                _ => None,
+            })
+            .collect())
+    }
+
+    async fn earliest_start_time(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+    ) -> Result<Option<i64>, StoreError> {
+        let options = FindOneOptions::builder()
+            .sort(doc! { "startTime": 1 })
+            .projection(doc! { "_id": 0, "startTime": 1 })
+            .build();
+        let earliest = self.history(dataset).find_one(scope, options).await?;
+        Ok(earliest
+            .as_ref()
+            .and_then(|document| document.get("startTime"))
+            .and_then(bson_to_f64)
+            .map(|start| start as i64))
+    }
+
+    async fn latest_start_time(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+    ) -> Result<Option<i64>, StoreError> {
+        let options = FindOneOptions::builder()
+            .sort(doc! { "startTime": -1 })
+            .projection(doc! { "_id": 0, "startTime": 1 })
+            .build();
+        let latest = self.history(dataset).find_one(scope, options).await?;
+        Ok(latest
+            .as_ref()
+            .and_then(|document| document.get("startTime"))
+            .and_then(bson_to_f64)
+            .map(|start| start as i64))
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        self.db.run_command(doc! { "ping": 1 }, None).await?;
+        Ok(())
+    }
+
+    async fn bucketed(
+        &self,
+        dataset: Dataset,
+        query: &BucketQuery,
+    ) -> Result<BucketPage, StoreError> {
+        let span = info_span!(
+            "mongo_aggregation",
+            collection = self.collections.history(dataset),
+            filter = %query.filter,
+            bucketing = ?query.bucketing,
+            skip = query.skip,
+            limit = query.limit,
+        );
+        let started = Instant::now();
+        let page = async {
+            self.aggregate(dataset, bucket_pipeline(query))
+                .await?
+                .try_collect::<Vec<_>>()
+                .await
+        };
+        let counts = async {
+            let mut counts = self.aggregate(dataset, count_pipeline(query)).await?;
+            Ok(counts.try_next().await?.unwrap_or_default())
+        };
+        let (documents, counts) = future::try_join(page, counts)
+            .instrument(span.clone())
+            .await?;
+        observe_aggregation(dataset, started);
+        let page = BucketPage {
+            documents,
+            total: facet_count(&counts, "total"),
+            remaining: facet_count(&counts, "remaining"),
+        };
+        span.in_scope(|| {
+            info!(
+                rows = page.documents.len(),
+                total = page.total,
+                duration_ms = started.elapsed().as_millis() as u64,
+                "Aggregated buckets"
+            )
+        });
+        Ok(page)
+    }
+
+    async fn stream_buckets(
+        &self,
+        dataset: Dataset,
+        query: &BucketQuery,
+    ) -> Result<BucketStream, StoreError> {
+        let span = info_span!(
+            "mongo_aggregation",
+            collection = self.collections.history(dataset),
+            filter = %query.filter,
+            bucketing = ?query.bucketing,
+            skip = query.skip,
+            limit = query.limit,
+        );
+        let started = Instant::now();
+        let rows = self
+            .aggregate(dataset, bucket_pipeline(query))
+            .instrument(span)
+            .await?;
+        observe_aggregation(dataset, started);
+        Ok(rows)
+    }
+
+    async fn load_checkpoint(&self, id: &str) -> Result<Option<SyncCheckpoint>, StoreError> {
+        Ok(self
+            .sync_checkpoints
+            .find_one(doc! { "_id": id }, None)
+            .await?)
+    }
+
+    async fn save_checkpoint(&self, checkpoint: &SyncCheckpoint) -> Result<(), StoreError> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.sync_checkpoints
+            .replace_one(doc! { "_id": &checkpoint.id }, checkpoint, options)
+            .await?;
+        Ok(())
+    }
+
+    async fn load_job(&self, dataset: Dataset) -> Result<Option<SyncJob>, StoreError> {
+        Ok(self
+            .sync_jobs
+            .find_one(doc! { "_id": dataset.name() }, None)
+            .await?)
+    }
+
+    async fn save_job(&self, job: &SyncJob) -> Result<(), StoreError> {
+        let options = ReplaceOptions::builder().upsert(true).build();
+        self.sync_jobs
+            .replace_one(doc! { "_id": &job.id }, job, options)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_jobs(&self) -> Result<Vec<SyncJob>, StoreError> {
+        let options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+        Ok(self
+            .sync_jobs
+            .find(doc! {}, options)
+            .await?
+            .try_collect()
+            .await?)
+    }
 }
@@ -0,0 +1,441 @@
+use async_trait::async_trait;
+use futures_util::{stream, StreamExt};
+use mongodb::bson::{Bson, Document};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use crate::db::store::{
+    bson_to_f64, interval_key, Accumulator, BucketPage, BucketQuery, BucketStream, HistoryStore,
+    Rollup, StoreError, UpsertSummary,
+};
+use crate::helpers::{cursor::Cursor, sort::SortKey, time_intervals::bucket_start};
+use crate::models::{checkpoint_model::SyncCheckpoint, dataset::Dataset, sync_job_model::SyncJob};
+
+// Keeps every dataset in process memory and evaluates bucket queries the same way the
+// MongoDB pipeline does, so the HTTP tests run without a database
+#[derive(Default)]
+pub struct InMemoryStore {
+    intervals: RwLock<HashMap<Dataset, Vec<Document>>>,
+    checkpoints: RwLock<HashMap<String, SyncCheckpoint>>,
+    jobs: RwLock<BTreeMap<String, SyncJob>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// Orders numbers by value and strings lexically; anything else compares equal
+pub fn compare_bson(a: &Bson, b: &Bson) -> Ordering {
+    match (bson_to_f64(a), bson_to_f64(b)) {
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => match (a.as_str(), b.as_str()) {
+            (Some(a), Some(b)) => a.cmp(b),
+            _ => Ordering::Equal,
+        },
+    }
+}
+
+// Evaluates the subset of the MongoDB query language used by the services
+pub fn matches_filter(document: &Document, filter: &Document) -> bool {
+    filter.iter().all(|(field, condition)| {
+        let value = document.get(field);
+        match condition {
+            Bson::Document(operators) if operators.keys().all(|key| key.starts_with('$')) => {
+                operators.iter().all(|(operator, operand)| {
+                    let ordering = value.map(|value| compare_bson(value, operand));
+                    match operator.as_str() {
+                        // Numbers compare by value whatever their BSON type, as in MongoDB
+                        "$eq" => value == Some(operand) || ordering == Some(Ordering::Equal),
+                        "$ne" => value != Some(operand) && ordering != Some(Ordering::Equal),
+                        "$nin" => operand.as_array().is_some_and(|operands| {
+                            operands.iter().all(|operand| {
+                                value.map(|value| compare_bson(value, operand))
+                                    != Some(Ordering::Equal)
+                            })
+                        }),
+                        "$gt" => ordering == Some(Ordering::Greater),
+                        "$gte" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                        "$lt" => ordering == Some(Ordering::Less),
+                        "$lte" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                        _ => false,
+                    }
+                })
+            }
+            expected => value.is_some_and(|value| {
+                value == expected || compare_bson(value, expected) == Ordering::Equal
+            }),
+        }
+    })
+}
+
+fn start_time(document: &Document) -> i64 {
+    document
+        .get("startTime")
+        .and_then(bson_to_f64)
+        .unwrap_or_default() as i64
+}
+
+fn keep_pool(document: &mut Document, pool: &str) {
+    if let Ok(pools) = document.get_array("pools") {
+        let pools: Vec<Bson> = pools
+            .iter()
+            .filter(|entry| {
+                entry
+                    .as_document()
+                    .and_then(|entry| entry.get_str("pool").ok())
+                    == Some(pool)
+            })
+            .cloned()
+            .collect();
+        document.insert("pools", pools);
+    }
+}
+
+fn numbers<'a>(intervals: &'a [Document], field: &'a str) -> impl Iterator<Item = f64> + 'a {
+    intervals.iter().map(move |interval| {
+        interval
+            .get(field)
+            .and_then(bson_to_f64)
+            .unwrap_or_default()
+    })
+}
+
+// Combines one field of the intervals of a bucket, mirroring the `$group` operators
+fn accumulate(intervals: &[Document], rollup: &Rollup) -> Option<Bson> {
+    let field = rollup.field;
+    let value = match rollup.accumulator {
+        Accumulator::First => intervals.first()?.get(field)?.clone(),
+        Accumulator::Last => intervals.last()?.get(field)?.clone(),
+        Accumulator::Sum => Bson::Double(numbers(intervals, field).sum()),
+        Accumulator::Avg => {
+            // Like `$avg`, intervals without the field are left out
+            let values: Vec<f64> = intervals
+                .iter()
+                .filter_map(|interval| interval.get(field).and_then(bson_to_f64))
+                .collect();
+            if values.is_empty() {
+                return None;
+            }
+            Bson::Double(values.iter().sum::<f64>() / values.len() as f64)
+        }
+        // Like `$min` and `$max`, intervals without the field are left out
+        Accumulator::Min | Accumulator::Max => {
+            let values = intervals
+                .iter()
+                .filter_map(|interval| interval.get(field).and_then(bson_to_f64));
+            let value = if rollup.accumulator == Accumulator::Min {
+                values.min_by(f64::total_cmp)
+            } else {
+                values.max_by(f64::total_cmp)
+            };
+            Bson::Double(value?)
+        }
+        Accumulator::WeightedAvg(weight) => {
+            let total_weight: f64 = numbers(intervals, weight).sum();
+            let weighted: f64 = numbers(intervals, field)
+                .zip(numbers(intervals, weight))
+                .map(|(value, weight)| value * weight)
+                .sum();
+            Bson::Double(if total_weight > 0.0 {
+                weighted / total_weight
+            } else {
+                0.0
+            })
+        }
+        Accumulator::SumByPool(_) => Bson::Array(merge_pools(
+            intervals.iter().filter_map(|interval| interval.get(field)),
+        )),
+    };
+    Some(value)
+}
+
+// Merges the `pools` arrays of several intervals into one entry per pool, summing the
+// numeric fields like the `SumByPool` stages of the MongoDB pipeline. Pools keep the
+// order in which they were first seen.
+pub fn merge_pools<'a>(arrays: impl IntoIterator<Item = &'a Bson>) -> Vec<Bson> {
+    fn collect<'a>(value: &'a Bson, entries: &mut Vec<&'a Document>) {
+        match value {
+            Bson::Array(values) => values.iter().for_each(|value| collect(value, entries)),
+            Bson::Document(entry) => entries.push(entry),
+            _ => {}
+        }
+    }
+
+    let mut entries = Vec::new();
+    arrays
+        .into_iter()
+        .for_each(|value| collect(value, &mut entries));
+
+    let mut merged: Vec<Document> = Vec::new();
+    for entry in entries {
+        let pool = entry.get("pool");
+        let Some(target) = merged.iter_mut().find(|merged| merged.get("pool") == pool) else {
+            merged.push(entry.clone());
+            continue;
+        };
+        for (field, value) in entry {
+            let sum = target
+                .get(field)
+                .and_then(bson_to_f64)
+                .zip(bson_to_f64(value))
+                .map(|(a, b)| a + b);
+            match sum {
+                Some(sum) => {
+                    target.insert(field, sum);
+                }
+                None if !target.contains_key(field) => {
+                    target.insert(field, value.clone());
+                }
+                None => {}
+            }
+        }
+    }
+    merged.into_iter().map(Bson::Document).collect()
+}
+
+fn compare_field(a: Option<&Bson>, b: Option<&Bson>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => compare_bson(a, b),
+        (Some(_), None) => Ordering::Greater,
+        (None, Some(_)) => Ordering::Less,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+// Orders buckets key by key, each in its own direction
+fn compare_buckets(a: &Document, b: &Document, sort: &[SortKey]) -> Ordering {
+    sort.iter().fold(Ordering::Equal, |ordering, key| {
+        ordering.then_with(|| {
+            let ordering = compare_field(a.get(&key.field), b.get(&key.field));
+            if key.order < 0 {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        })
+    })
+}
+
+fn is_after(document: &Document, cursor: &Cursor) -> bool {
+    let mut position = Document::new();
+    for (key, value) in cursor.position() {
+        position.insert(key.field.as_str(), value.clone());
+    }
+    compare_buckets(document, &position, &cursor.sort) == Ordering::Greater
+}
+
+pub fn bucket(documents: Vec<Document>, query: &BucketQuery) -> BucketPage {
+    let mut documents: Vec<Document> = documents
+        .into_iter()
+        .filter(|document| matches_filter(document, &query.filter))
+        .collect();
+    if let Some(pool) = &query.pools_filter {
+        documents
+            .iter_mut()
+            .for_each(|document| keep_pool(document, pool));
+    }
+    documents.sort_by_key(start_time);
+
+    let mut buckets: BTreeMap<i64, Vec<Document>> = BTreeMap::new();
+    for document in documents {
+        let start = start_time(&document);
+        buckets
+            .entry(bucket_start(&query.bucketing, start))
+            .or_default()
+            .push(document);
+    }
+
+    let mut results: Vec<Document> = buckets
+        .into_values()
+        .map(|intervals| {
+            let mut rolled = Document::new();
+            for rollup in &query.rollup {
+                if let Some(value) = accumulate(&intervals, rollup) {
+                    rolled.insert(rollup.name, value);
+                }
+            }
+            rolled
+        })
+        .filter(|rolled| matches_filter(rolled, &query.conditions))
+        .collect();
+
+    results.sort_by(|a, b| compare_buckets(a, b, &query.sort));
+
+    let total = results.len() as u64;
+    let remaining: Vec<Document> = results
+        .into_iter()
+        .filter(|document| match &query.after {
+            Some(cursor) => is_after(document, cursor),
+            None => true,
+        })
+        .skip(query.skip.max(0) as usize)
+        .collect();
+    BucketPage {
+        remaining: remaining.len() as u64,
+        documents: remaining
+            .into_iter()
+            .take(query.limit.max(0) as usize)
+            .collect(),
+        total,
+    }
+}
+
+#[async_trait]
+impl HistoryStore for InMemoryStore {
+    async fn upsert(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+        intervals: Vec<Document>,
+    ) -> Result<UpsertSummary, StoreError> {
+        let mut store = self.intervals.write().unwrap();
+        let stored = store.entry(dataset).or_default();
+        let key = interval_key(dataset);
+        let mut summary = UpsertSummary::default();
+
+        for mut document in intervals {
+            document.extend(scope.clone());
+            let existing = stored.iter_mut().find(|candidate| {
+                key.iter()
+                    .all(|field| candidate.get(field) == document.get(field))
+            });
+            match existing {
+                Some(existing) => {
+                    existing.extend(document);
+                    summary.matched += 1;
+                }
+                None => {
+                    stored.push(document);
+                    summary.upserted += 1;
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    async fn start_times(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<i64>, StoreError> {
+        let store = self.intervals.read().unwrap();
+        Ok(store
+            .get(&dataset)
+            .map(|stored| {
+                stored
+                    .iter()
+                    .filter(|document| matches_filter(document, &scope))
+                    .map(start_time)
+                    .filter(|start| *start >= from && *start < to)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn depth_pools(&self) -> Result<Vec<String>, StoreError> {
+        let store = self.intervals.read().unwrap();
+        let mut pools: Vec<String> = store
+            .get(&Dataset::Depths)
+            .map(|stored| {
+                stored
+                    .iter()
+                    .filter_map(|document| document.get_str("pool").ok().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default();
+        pools.sort();
+        pools.dedup();
+        Ok(pools)
+    }
+
+    async fn earliest_start_time(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+    ) -> Result<Option<i64>, StoreError> {
+        let store = self.intervals.read().unwrap();
+        Ok(store.get(&dataset).and_then(|stored| {
+            stored
+                .iter()
+                .filter(|document| matches_filter(document, &scope))
+                .map(start_time)
+                .min()
+        }))
+    }
+
+    async fn latest_start_time(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+    ) -> Result<Option<i64>, StoreError> {
+        let store = self.intervals.read().unwrap();
+        Ok(store.get(&dataset).and_then(|stored| {
+            stored
+                .iter()
+                .filter(|document| matches_filter(document, &scope))
+                .map(start_time)
+                .max()
+        }))
+    }
+
+    async fn ping(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn bucketed(
+        &self,
+        dataset: Dataset,
+        query: &BucketQuery,
+    ) -> Result<BucketPage, StoreError> {
+        let documents = self
+            .intervals
+            .read()
+            .unwrap()
+            .get(&dataset)
+            .cloned()
+            .unwrap_or_default();
+        Ok(bucket(documents, query))
+    }
+
+    async fn stream_buckets(
+        &self,
+        dataset: Dataset,
+        query: &BucketQuery,
+    ) -> Result<BucketStream, StoreError> {
+        let page = self.bucketed(dataset, query).await?;
+        Ok(stream::iter(page.documents.into_iter().map(Ok)).boxed())
+    }
+
+    async fn load_checkpoint(&self, id: &str) -> Result<Option<SyncCheckpoint>, StoreError> {
+        Ok(self.checkpoints.read().unwrap().get(id).cloned())
+    }
+
+    async fn save_checkpoint(&self, checkpoint: &SyncCheckpoint) -> Result<(), StoreError> {
+        self.checkpoints
+            .write()
+            .unwrap()
+            .insert(checkpoint.id.clone(), checkpoint.clone());
+        Ok(())
+    }
+
+    async fn load_job(&self, dataset: Dataset) -> Result<Option<SyncJob>, StoreError> {
+        Ok(self.jobs.read().unwrap().get(dataset.name()).cloned())
+    }
+
+    async fn save_job(&self, job: &SyncJob) -> Result<(), StoreError> {
+        self.jobs
+            .write()
+            .unwrap()
+            .insert(job.id.clone(), job.clone());
+        Ok(())
+    }
+
+    async fn list_jobs(&self) -> Result<Vec<SyncJob>, StoreError> {
+        Ok(self.jobs.read().unwrap().values().cloned().collect())
+    }
+}
@@ -1 +1,4 @@
 pub mod connection;
+#[cfg(test)]
+pub mod memory;
+pub mod store;
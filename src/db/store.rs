@@ -0,0 +1,280 @@
+use async_trait::async_trait;
+use futures_util::stream::BoxStream;
+use mongodb::bson::{from_document, to_document, Bson, Document};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+
+use crate::helpers::{cursor::Cursor, sort::SortKey, time_intervals::Bucketing};
+use crate::models::{checkpoint_model::SyncCheckpoint, dataset::Dataset, sync_job_model::SyncJob};
+
+#[derive(Debug)]
+pub struct StoreError(pub String);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<mongodb::error::Error> for StoreError {
+    fn from(e: mongodb::error::Error) -> Self {
+        StoreError(e.to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct UpsertSummary {
+    pub matched: u64,
+    pub upserted: u64,
+}
+
+// How a field is combined when hourly intervals are rolled up into one bucket.
+// Intervals are fed to the accumulator in ascending `startTime` order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Accumulator {
+    First,
+    Last,
+    Sum,
+    Avg,
+    Min,
+    Max,
+    // Average weighted by another field of the same interval, e.g. a slip by its volume
+    WeightedAvg(&'static str),
+    // Sums these fields of the entries of a `pools` array that share the same `pool`. A
+    // rollup holds at most one.
+    SumByPool(&'static [&'static str]),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rollup {
+    pub field: &'static str,
+    // Field of the bucket the result is written to, `field` unless renamed with `named`
+    pub name: &'static str,
+    pub accumulator: Accumulator,
+}
+
+impl Rollup {
+    pub fn first(field: &'static str) -> Self {
+        Self {
+            field,
+            name: field,
+            accumulator: Accumulator::First,
+        }
+    }
+
+    pub fn last(field: &'static str) -> Self {
+        Self {
+            field,
+            name: field,
+            accumulator: Accumulator::Last,
+        }
+    }
+
+    pub fn sum(field: &'static str) -> Self {
+        Self {
+            field,
+            name: field,
+            accumulator: Accumulator::Sum,
+        }
+    }
+
+    pub fn avg(field: &'static str) -> Self {
+        Self {
+            field,
+            name: field,
+            accumulator: Accumulator::Avg,
+        }
+    }
+
+    pub fn weighted_avg(field: &'static str, weight: &'static str) -> Self {
+        Self {
+            field,
+            name: field,
+            accumulator: Accumulator::WeightedAvg(weight),
+        }
+    }
+
+    pub fn sum_by_pool(field: &'static str, fields: &'static [&'static str]) -> Self {
+        Self {
+            field,
+            name: field,
+            accumulator: Accumulator::SumByPool(fields),
+        }
+    }
+
+    pub fn min(field: &'static str) -> Self {
+        Self {
+            field,
+            name: field,
+            accumulator: Accumulator::Min,
+        }
+    }
+
+    pub fn max(field: &'static str) -> Self {
+        Self {
+            field,
+            name: field,
+            accumulator: Accumulator::Max,
+        }
+    }
+
+    pub fn named(self, name: &'static str) -> Self {
+        Self { name, ..self }
+    }
+}
+
+// A range query over one dataset, bucketed into calendar intervals
+#[derive(Debug, Clone)]
+pub struct BucketQuery {
+    // Applied to the stored hourly intervals: the date range and the pool they belong to
+    pub filter: Document,
+    // Applied to the rolled-up buckets, before they are sorted and paged; supports equality
+    // and $gt/$gte/$lt/$lte/$ne/$nin
+    pub conditions: Document,
+    // Keeps only the entries of the `pools` array that belong to this pool
+    pub pools_filter: Option<String>,
+    pub bucketing: Bucketing,
+    pub rollup: Vec<Rollup>,
+    // Applied to the buckets; ends with `startTime` so positions are unique
+    pub sort: Vec<SortKey>,
+    pub skip: i64,
+    pub limit: i64,
+    // Only buckets sorting after this one, for keyset pagination
+    pub after: Option<Cursor>,
+}
+
+// One page of buckets plus the counts needed for pagination
+#[derive(Debug, Default)]
+pub struct BucketPage {
+    pub documents: Vec<Document>,
+    // Buckets matching the query, across all pages
+    pub total: u64,
+    // Buckets from the start of this page (after the cursor or skipped pages) to the end
+    pub remaining: u64,
+}
+
+impl BucketPage {
+    pub fn has_next_page(&self) -> bool {
+        self.remaining > self.documents.len() as u64
+    }
+
+    pub fn total_pages(&self, count: i64) -> u64 {
+        self.total.div_ceil(count.max(1) as u64)
+    }
+
+    // Token for the page after this one
+    pub fn next_cursor(&self, query: &BucketQuery) -> Option<String> {
+        if !self.has_next_page() {
+            return None;
+        }
+        Some(Cursor::after(self.documents.last()?, &query.sort).encode())
+    }
+}
+
+// Buckets read one by one, for exports
+pub type BucketStream = BoxStream<'static, Result<Document, StoreError>>;
+
+#[async_trait]
+pub trait HistoryStore: Send + Sync {
+    // Inserts or replaces intervals keyed on `startTime` plus the fields in `scope`,
+    // which are also stored on every document
+    async fn upsert(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+        intervals: Vec<Document>,
+    ) -> Result<UpsertSummary, StoreError>;
+
+    // `startTime` of every stored interval in `[from, to)` matching `scope`
+    async fn start_times(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<i64>, StoreError>;
+
+    // Pools with stored depth intervals
+    async fn depth_pools(&self) -> Result<Vec<String>, StoreError>;
+
+    // `startTime` of the oldest stored interval matching `scope`
+    async fn earliest_start_time(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+    ) -> Result<Option<i64>, StoreError>;
+
+    // `startTime` of the newest stored interval matching `scope`
+    async fn latest_start_time(
+        &self,
+        dataset: Dataset,
+        scope: Document,
+    ) -> Result<Option<i64>, StoreError>;
+
+    // Checks that the store is reachable
+    async fn ping(&self) -> Result<(), StoreError>;
+
+    async fn bucketed(
+        &self,
+        dataset: Dataset,
+        query: &BucketQuery,
+    ) -> Result<BucketPage, StoreError>;
+
+    // The buckets `bucketed` would return for `query`, streamed in one pass without the
+    // pagination counts
+    async fn stream_buckets(
+        &self,
+        dataset: Dataset,
+        query: &BucketQuery,
+    ) -> Result<BucketStream, StoreError>;
+
+    async fn load_checkpoint(&self, id: &str) -> Result<Option<SyncCheckpoint>, StoreError>;
+
+    async fn save_checkpoint(&self, checkpoint: &SyncCheckpoint) -> Result<(), StoreError>;
+
+    async fn load_job(&self, dataset: Dataset) -> Result<Option<SyncJob>, StoreError>;
+
+    async fn save_job(&self, job: &SyncJob) -> Result<(), StoreError>;
+
+    // Every job that has been registered, ordered by dataset
+    async fn list_jobs(&self) -> Result<Vec<SyncJob>, StoreError>;
+}
+
+// Fields that identify one hourly interval of a dataset
+pub fn interval_key(dataset: Dataset) -> Vec<&'static str> {
+    match dataset {
+        Dataset::Depths => vec!["pool", "startTime"],
+        Dataset::Earnings | Dataset::Members | Dataset::Swaps => vec!["startTime"],
+    }
+}
+
+pub fn bson_to_f64(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Double(v) => Some(*v),
+        Bson::Int64(v) => Some(*v as f64),
+        Bson::Int32(v) => Some(*v as f64),
+        _ => None,
+    }
+}
+
+pub fn to_documents<T: Serialize>(intervals: &[T]) -> Result<Vec<Document>, StoreError> {
+    intervals
+        .iter()
+        .map(|interval| {
+            to_document(interval)
+                .map_err(|e| StoreError(format!("Error serializing interval: {}", e)))
+        })
+        .collect()
+}
+
+pub fn from_documents<T: DeserializeOwned>(documents: Vec<Document>) -> Result<Vec<T>, StoreError> {
+    documents
+        .into_iter()
+        .map(|document| {
+            from_document(document)
+                .map_err(|e| StoreError(format!("Error decoding interval: {}", e)))
+        })
+        .collect()
+}
@@ -0,0 +1,88 @@
+use actix_web::{
+    error::{InternalError, QueryPayloadError},
+    http::StatusCode,
+    web, HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::db::store::StoreError;
+
+// Error body returned by every endpoint:
+// `{ "code": "...", "message": "...", "field": "...", "details": ... }`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiError {
+    #[serde(skip)]
+    #[schema(ignore)]
+    pub status: StatusCode,
+    // Stable, machine-readable identifier such as `invalid_parameter`
+    #[schema(value_type = String)]
+    pub code: &'static str,
+    pub message: String,
+    // Query parameter the error refers to, if any
+    #[schema(value_type = Option<String>)]
+    pub field: Option<&'static str>,
+    // Boxed to keep `Result<_, ApiError>` small
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<Box<Value>>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            field: None,
+            details: None,
+        }
+    }
+
+    pub fn invalid_parameter(field: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_parameter", message).with_field(field)
+    }
+
+    pub fn with_field(mut self, field: &'static str) -> Self {
+        self.field = Some(field);
+        self
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(self)
+    }
+}
+
+// The store error is only logged, it can carry connection and query details
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        error!(error = %e, "Database error");
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "database_error",
+            "The history store could not be queried.",
+        )
+    }
+}
+
+// Reports query strings that don't deserialize (e.g. `min_depth=abc`) in the same format
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|e: QueryPayloadError, _| {
+        let error = ApiError::new(StatusCode::BAD_REQUEST, "invalid_query", e.to_string());
+        InternalError::from_response(e, error.error_response()).into()
+    })
+}
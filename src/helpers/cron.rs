@@ -1,44 +1,347 @@
-use std::error::Error;
-use chrono::{Duration, Utc};
 use crate::{
-    db::connection::MongoDB,
-    services::{
-        depths_service::update_depths_data, earnings_service::update_earnings_history,
-        rpmuh_service::update_rpmuh_data, swaps_service::update_swaps_history,
-    },
+    config::{JobConfig, SchedulerConfig},
+    db::store::HistoryStore,
+    helpers::gaps::align_to_hour,
+    helpers::metrics::mark_synced,
+    helpers::shutdown::Shutdown,
+    midgard::client::MidgardClient,
+    models::{dataset::Dataset, sync_job_model::SyncJob},
+    services::backfill_service::{discover_pools, fill_gaps, history_start, GapReport},
 };
+use chrono::Utc;
+use croner::{errors::CronError, Cron};
+use std::{
+    error::Error,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use tracing::{error, info, instrument, warn};
 
-pub async fn start_scheduler(mongo_db: MongoDB) -> Result<(), Box<dyn Error>> {
-    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+// Reads a cron expression of six or seven fields, seconds first. Config validation, the job
+// timers and `next_run_at` all go through it, so they agree on every tick.
+pub fn parse_cron(expression: &str) -> Result<Cron, CronError> {
+    Cron::new(expression)
+        .with_seconds_required()
+        .with_dom_and_dow()
+        .parse()
+}
 
-    loop {
-        interval.tick().await;
-        println!("Fetching Latest Data");
-        if let Err(e) = pull_latest_data(mongo_db.clone()).await {
-            println!("Error pulling latest data: {}", e);
+// Starts one job per enabled dataset and runs them until shutdown is requested, then
+// waits for the runs in progress, which stop after the range they are fetching
+pub async fn start_scheduler(
+    store: Arc<dyn HistoryStore>,
+    midgard: MidgardClient,
+    config: SchedulerConfig,
+    mut shutdown: Shutdown,
+) -> Result<(), Box<dyn Error>> {
+    let mut runners = Vec::new();
+    let mut timers = Vec::new();
+    for dataset in Dataset::ALL {
+        let job_config = config.jobs.get(dataset);
+        if !job_config.enabled {
+            info!(%dataset, "Sync job is disabled");
+            continue;
         }
+        let runner = Arc::new(
+            SyncJobRunner::register(
+                store.clone(),
+                midgard.clone(),
+                dataset,
+                job_config,
+                config.lookback_hours,
+                shutdown.clone(),
+            )
+            .await?,
+        );
+        timers.push(tokio::spawn(runner.clone().tick(shutdown.clone())));
+        info!(%dataset, cron = %job_config.cron, "Sync job scheduled");
+        runners.push(runner);
+    }
+
+    shutdown.requested().await;
+    for timer in timers {
+        timer.await?;
+    }
+    while runners.iter().any(|runner| runner.is_running()) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
     }
+    info!("Scheduler stopped");
+    Ok(())
+}
+
+// Runs the job of one dataset and keeps its `sync_jobs` document up to date. A tick that
+// comes while the previous run is still going is skipped rather than run alongside it.
+struct SyncJobRunner {
+    dataset: Dataset,
+    store: Arc<dyn HistoryStore>,
+    midgard: MidgardClient,
+    schedule: Cron,
+    timeout: Duration,
+    lookback_hours: i64,
+    shutdown: Shutdown,
+    running: AtomicBool,
+    state: Mutex<SyncJob>,
 }
 
-async fn pull_latest_data(mongo_db: MongoDB) -> Result<(), Box<dyn Error>> {
-    let to = Utc::now().timestamp() as f64;
-    let from = (Utc::now() - Duration::hours(1)).timestamp() as f64;
+impl SyncJobRunner {
+    // Picks up the counters of earlier processes. A run they left marked as running was
+    // cut short, so it is cleared.
+    async fn register(
+        store: Arc<dyn HistoryStore>,
+        midgard: MidgardClient,
+        dataset: Dataset,
+        config: &JobConfig,
+        lookback_hours: i64,
+        shutdown: Shutdown,
+    ) -> Result<Self, Box<dyn Error>> {
+        let schedule = parse_cron(&config.cron)?;
+        let mut state = match store.load_job(dataset).await {
+            Ok(state) => state.unwrap_or_default(),
+            Err(e) => {
+                warn!(%dataset, error = %e, "Unable to load the sync job state, starting afresh");
+                SyncJob::default()
+            }
+        };
+        state.id = dataset.name().to_string();
+        state.dataset = dataset.name().to_string();
+        state.cron = config.cron.clone();
+        state.timeout_secs = config.timeout_secs as i64;
+        state.running = false;
 
-    if let Err(e) = update_depths_data(mongo_db.clone(), String::from("BTC.BTC"), from, to).await {
-        println!("Error fetching depth history: {:?}", e);
+        let runner = Self {
+            dataset,
+            store,
+            midgard,
+            schedule,
+            timeout: Duration::from_secs(config.timeout_secs),
+            lookback_hours,
+            shutdown,
+            running: AtomicBool::new(false),
+            state: Mutex::new(state),
+        };
+        let next_run_at = runner.next_run_at();
+        runner.update(|job| job.next_run_at = next_run_at).await;
+        Ok(runner)
     }
 
-    if let Err(e) = update_earnings_history(mongo_db.clone(), from, to).await {
-        println!("Error fetching earnings history: {:?}", e);
+    // Starts a run at every tick of the schedule until shutdown is requested. Runs are
+    // spawned, so a long one doesn't hold back the ticks that are skipped meanwhile.
+    async fn tick(self: Arc<Self>, mut shutdown: Shutdown) {
+        let mut after = Utc::now();
+        loop {
+            // Following the previous tick rather than the clock, as a timer that wakes a
+            // little early would otherwise land on the same tick again. Ticks missed while
+            // the process was suspended are dropped.
+            let next = match self.schedule.find_next_occurrence(&after, false) {
+                Ok(next) => next,
+                Err(e) => {
+                    error!(dataset = %self.dataset, error = %e, "No next run in the schedule");
+                    return;
+                }
+            };
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            tokio::select! {
+                _ = shutdown.requested() => return,
+                _ = tokio::time::sleep_until(tokio::time::Instant::now() + wait) => {}
+            }
+            after = next.max(Utc::now());
+
+            // Claimed before spawning, so the shutdown wait in `start_scheduler` sees the run
+            let runner = self.clone();
+            if self.running.swap(true, Ordering::SeqCst) {
+                tokio::spawn(async move { runner.skip().await });
+            } else {
+                tokio::spawn(async move { runner.run().await });
+            }
+        }
     }
 
-    if let Err(e) = update_rpmuh_data(mongo_db.clone(), from, to).await {
-        println!("Error fetching members history: {:?}", e);
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
     }
 
-    if let Err(e) = update_swaps_history(mongo_db.clone(), from, to).await {
-        println!("Error fetching swap history: {:?}", e);
+    fn next_run_at(&self) -> Option<i64> {
+        self.schedule
+            .find_next_occurrence(&Utc::now(), false)
+            .ok()
+            .map(|next| next.timestamp())
+    }
+
+    // Applies `change` to the state and stores it; a failed save is logged and the run goes on
+    async fn update(&self, change: impl FnOnce(&mut SyncJob)) {
+        let mut state = self.state.lock().await;
+        change(&mut state);
+        if let Err(e) = self.store.save_job(&state).await {
+            error!(dataset = %self.dataset, error = %e, "Error saving the sync job state");
+        }
+    }
+
+    async fn skip(&self) {
+        warn!(dataset = %self.dataset, "Previous run is still going, skipping this one");
+        let next_run_at = self.next_run_at();
+        self.update(|job| {
+            job.skipped += 1;
+            job.next_run_at = next_run_at;
+        })
+        .await;
+    }
+
+    // Expects `running` to be claimed by the caller, and releases it when done
+    async fn run(&self) {
+        let dataset = self.dataset;
+        if self.shutdown.is_requested() {
+            self.running.store(false, Ordering::SeqCst);
+            return;
+        }
+        let next_run_at = self.next_run_at();
+
+        let started = Instant::now();
+        let started_at = Utc::now().timestamp();
+        self.update(|job| {
+            job.running = true;
+            job.last_started_at = Some(started_at);
+            job.next_run_at = next_run_at;
+        })
+        .await;
+
+        let sync = sync_dataset(
+            self.store.as_ref(),
+            &self.midgard,
+            dataset,
+            self.lookback_hours,
+            &self.shutdown,
+        );
+        let result = match tokio::time::timeout(self.timeout, sync).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("Timed out after {}s", self.timeout.as_secs())),
+        };
+
+        let finished_at = Utc::now().timestamp();
+        let duration_ms = started.elapsed().as_millis() as i64;
+        match &result {
+            Ok(inserted) => info!(%dataset, inserted, duration_ms, "Sync job finished"),
+            Err(e) => error!(%dataset, error = %e, duration_ms, "Sync job failed"),
+        }
+        self.update(|job| {
+            job.running = false;
+            job.runs += 1;
+            job.last_finished_at = Some(finished_at);
+            job.last_duration_ms = Some(duration_ms);
+            match result {
+                Ok(inserted) => {
+                    job.last_success_at = Some(finished_at);
+                    job.last_inserted = Some(inserted as i64);
+                    job.last_error = None;
+                }
+                Err(e) => {
+                    job.failures += 1;
+                    job.last_error = Some(e);
+                }
+            }
+        })
+        .await;
+        self.running.store(false, Ordering::SeqCst);
     }
+}
 
+// Runs every dataset once, one after the other
+pub async fn pull_latest_data(
+    store: &dyn HistoryStore,
+    midgard: &MidgardClient,
+    lookback_hours: i64,
+    shutdown: &Shutdown,
+) -> Result<(), Box<dyn Error>> {
+    for dataset in Dataset::ALL {
+        if let Err(e) = sync_dataset(store, midgard, dataset, lookback_hours, shutdown).await {
+            error!(%dataset, error = %e, "Sync failed");
+        }
+    }
     Ok(())
 }
+
+// Fills every missing hourly bucket of `dataset` from its oldest stored interval up to the
+// hour that just completed, so downtime of any length is caught up. With nothing stored yet,
+// or nothing older, the last `lookback_hours` are filled. Depths are filled for every
+// available pool. Returns the number of new intervals, or why some buckets are still
+// missing. Stops between ranges once shutdown is requested.
+#[instrument(skip_all, fields(%dataset))]
+pub async fn sync_dataset(
+    store: &dyn HistoryStore,
+    midgard: &MidgardClient,
+    dataset: Dataset,
+    lookback_hours: i64,
+    shutdown: &Shutdown,
+) -> Result<u64, String> {
+    let to = align_to_hour(Utc::now().timestamp());
+    let recent = to - lookback_hours * 3600;
+
+    let pools = match dataset {
+        Dataset::Depths => {
+            let pools = discover_pools(store, midgard).await;
+            if pools.is_empty() {
+                return Err(String::from("No pools to ingest depths for"));
+            }
+            info!(pools = pools.len(), "Ingesting depths");
+            pools.into_iter().map(Some).collect()
+        }
+        Dataset::Earnings | Dataset::Members | Dataset::Swaps => vec![None],
+    };
+
+    let mut inserted = 0;
+    let mut missing = 0;
+    let mut problems = Vec::new();
+    for pool in &pools {
+        let pool = pool.as_deref();
+        // The scan reads `startTime` alone over the unique interval index
+        let from = match history_start(store, dataset, pool).await {
+            Ok(start) => start.map_or(recent, |start| start.min(recent)),
+            Err(e) => {
+                error!(pool, error = %e.0, "Error reading the start of the history");
+                problems.push(format!("Error reading the start of the history: {}", e.0));
+                continue;
+            }
+        };
+        match fill_gaps(store, midgard, dataset, pool, from, to, shutdown).await {
+            Ok(report) => {
+                log_gap_report(&report);
+                inserted += report.inserted;
+                missing += report.gaps.len() - report.filled.len();
+            }
+            Err(e) => {
+                error!(pool, error = %e.0, "Error scanning history for gaps");
+                problems.push(format!("Error scanning history for gaps: {}", e.0));
+            }
+        }
+    }
+    if missing > 0 {
+        problems.push(format!("Gaps still missing: {}", missing));
+    }
+
+    if problems.is_empty() {
+        mark_synced(dataset, Utc::now().timestamp());
+        Ok(inserted)
+    } else {
+        problems.dedup();
+        Err(problems.join("; "))
+    }
+}
+
+fn log_gap_report(report: &GapReport) {
+    let pool = report.pool.as_deref();
+    info!(
+        dataset = %report.dataset,
+        pool,
+        gaps = report.gaps.len(),
+        missing_hours = report.missing_hours(),
+        filled = report.filled.len(),
+        filled_hours = report.filled_hours(),
+        inserted = report.inserted,
+        failed = report.failed.len(),
+        "Filled gaps"
+    );
+    for gap in &report.failed {
+        warn!(dataset = %report.dataset, pool, %gap, "Gap is still missing");
+    }
+}
@@ -0,0 +1,57 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use mongodb::bson::{Bson, Document};
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::{api_error::ApiError, sort::SortKey};
+
+// Position of the last bucket of a page. The next page holds the buckets that sort after
+// it, with `startTime` breaking ties, so pages stay stable while new hours are ingested.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub sort: Vec<SortKey>,
+    // Value of each sort key in the last bucket
+    pub values: Vec<Bson>,
+}
+
+impl Cursor {
+    // Cursor pointing after `document`, the last bucket of a page
+    pub fn after(document: &Document, sort: &[SortKey]) -> Self {
+        Self {
+            sort: sort.to_vec(),
+            values: sort
+                .iter()
+                .map(|key| document.get(&key.field).cloned().unwrap_or(Bson::Null))
+                .collect(),
+        }
+    }
+
+    // The sort keys with the position of the last bucket on each
+    pub fn position(&self) -> impl Iterator<Item = (&SortKey, &Bson)> {
+        self.sort.iter().zip(&self.values)
+    }
+
+    // Opaque token handed out as `next_cursor`
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursor serializes to JSON");
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    pub fn decode(token: &str) -> Result<Self, ApiError> {
+        URL_SAFE_NO_PAD
+            .decode(token)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| ApiError::invalid_parameter("cursor", "Invalid cursor."))
+    }
+
+    // A cursor only makes sense for the sort it was issued for
+    pub fn check_sort(&self, sort: &[SortKey]) -> Result<(), ApiError> {
+        if self.sort != sort || self.values.len() != sort.len() {
+            return Err(ApiError::invalid_parameter(
+                "cursor",
+                "Cursor was issued for a different sort.",
+            ));
+        }
+        Ok(())
+    }
+}
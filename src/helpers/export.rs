@@ -0,0 +1,367 @@
+use actix_web::{http::header::ACCEPT, web::Bytes, HttpRequest};
+use mongodb::bson::{Bson, Document};
+use parquet::{
+    data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use utoipa::{
+    openapi::{
+        schema::{SchemaType, Type},
+        RefOr, Schema,
+    },
+    ToSchema,
+};
+
+use crate::helpers::api_error::ApiError;
+
+// Response encodings of the history endpoints, picked with `format` or the `Accept` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Ndjson,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn from_request(format: Option<&str>, req: &HttpRequest) -> Result<Self, ApiError> {
+        if let Some(format) = format {
+            return match format {
+                "json" => Ok(ExportFormat::Json),
+                "csv" => Ok(ExportFormat::Csv),
+                "ndjson" => Ok(ExportFormat::Ndjson),
+                "parquet" => Ok(ExportFormat::Parquet),
+                _ => Err(ApiError::invalid_parameter(
+                    "format",
+                    "Invalid format, expected one of json, csv, ndjson, parquet.",
+                )),
+            };
+        }
+        let accept = req
+            .headers()
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or_default();
+        Ok([
+            ExportFormat::Csv,
+            ExportFormat::Ndjson,
+            ExportFormat::Parquet,
+        ]
+        .into_iter()
+        .find(|format| accept.contains(format.content_type()))
+        .unwrap_or(ExportFormat::Json))
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    // Exports aren't paged, so `count` only caps JSON responses
+    pub fn max_count(&self, json_max: i64) -> i64 {
+        match self {
+            ExportFormat::Json => json_max,
+            _ => i64::MAX,
+        }
+    }
+}
+
+// One row of an export, in the order of `columns`
+fn row(document: Document, columns: &[Column]) -> Vec<Value> {
+    let mut json = Bson::Document(document).into_relaxed_extjson();
+    columns
+        .iter()
+        .map(|column| {
+            json.get_mut(column.name)
+                .map(Value::take)
+                .unwrap_or(Value::Null)
+        })
+        .collect()
+}
+
+// Nested values such as the earnings `pools` become JSON text in flat formats
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Int64,
+    Double,
+    Text,
+}
+
+impl ColumnType {
+    // Integers and numbers, optional or not, keep their type; lists and objects are
+    // written as JSON text
+    fn of_schema(schema: &RefOr<Schema>) -> Self {
+        let RefOr::T(Schema::Object(object)) = schema else {
+            return ColumnType::Text;
+        };
+        let types = match &object.schema_type {
+            SchemaType::Type(schema_type) => vec![schema_type.clone()],
+            SchemaType::Array(types) => types.clone(),
+            SchemaType::AnyValue => Vec::new(),
+        };
+        if types.contains(&Type::Integer) {
+            ColumnType::Int64
+        } else if types.contains(&Type::Number) {
+            ColumnType::Double
+        } else {
+            ColumnType::Text
+        }
+    }
+
+    fn parquet_type(&self) -> &'static str {
+        match self {
+            ColumnType::Int64 => "INT64",
+            ColumnType::Double => "DOUBLE",
+            ColumnType::Text => "BYTE_ARRAY (UTF8)",
+        }
+    }
+}
+
+// A column of an export, typed after the model field it is read from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Column {
+    pub name: &'static str,
+    pub kind: ColumnType,
+}
+
+// Columns for `fields` of the model `T`. Their types come from its OpenAPI schema rather than
+// from the rows, so they don't depend on which values a chunk happens to hold.
+pub fn columns<T: ToSchema>(fields: Vec<&'static str>) -> Vec<Column> {
+    let mut schemas = Vec::new();
+    T::schemas(&mut schemas);
+    let mut types = HashMap::new();
+    collect_types(&T::schema(), &schemas, &mut types);
+    fields
+        .into_iter()
+        .map(|name| Column {
+            name,
+            kind: types.get(name).copied().unwrap_or(ColumnType::Text),
+        })
+        .collect()
+}
+
+// Reads the property types of an object schema, following `$ref`s to the schemas of `T` and
+// the `allOf` that `#[serde(flatten)]` produces
+fn collect_types(
+    schema: &RefOr<Schema>,
+    schemas: &[(String, RefOr<Schema>)],
+    types: &mut HashMap<String, ColumnType>,
+) {
+    match schema {
+        RefOr::Ref(reference) => {
+            let name = reference.ref_location.rsplit('/').next();
+            if let Some((_, schema)) = schemas.iter().find(|(n, _)| Some(n.as_str()) == name) {
+                collect_types(schema, schemas, types);
+            }
+        }
+        RefOr::T(Schema::AllOf(all_of)) => {
+            for item in &all_of.items {
+                collect_types(item, schemas, types);
+            }
+        }
+        RefOr::T(Schema::Object(object)) => {
+            for (name, property) in &object.properties {
+                types.insert(name.clone(), ColumnType::of_schema(property));
+            }
+        }
+        RefOr::T(_) => {}
+    }
+}
+
+type ParquetWriter = SerializedFileWriter<Vec<u8>>;
+
+// Turns chunks of buckets into response body chunks. Parquet writes one row group per chunk.
+pub enum Encoder {
+    Csv {
+        columns: Vec<Column>,
+        header_written: bool,
+    },
+    Ndjson {
+        columns: Vec<Column>,
+    },
+    Parquet {
+        name: &'static str,
+        columns: Vec<Column>,
+        writer: Option<Box<ParquetWriter>>,
+    },
+}
+
+impl Encoder {
+    pub fn new(format: ExportFormat, name: &'static str, columns: Vec<Column>) -> Self {
+        match format {
+            ExportFormat::Csv => Encoder::Csv {
+                columns,
+                header_written: false,
+            },
+            ExportFormat::Parquet => Encoder::Parquet {
+                name,
+                columns,
+                writer: None,
+            },
+            // JSON is answered by the handlers themselves and never streamed
+            ExportFormat::Json | ExportFormat::Ndjson => Encoder::Ndjson { columns },
+        }
+    }
+
+    pub fn encode(&mut self, documents: Vec<Document>) -> Result<Bytes, String> {
+        match self {
+            Encoder::Csv {
+                columns,
+                header_written,
+            } => {
+                let mut csv = csv::Writer::from_writer(Vec::new());
+                if !*header_written {
+                    csv.write_record(columns.iter().map(|column| column.name))
+                        .map_err(|e| e.to_string())?;
+                    *header_written = true;
+                }
+                for document in documents {
+                    let cells = row(document, columns);
+                    csv.write_record(cells.iter().map(cell))
+                        .map_err(|e| e.to_string())?;
+                }
+                csv.into_inner().map(Bytes::from).map_err(|e| e.to_string())
+            }
+            Encoder::Ndjson { columns } => {
+                let mut lines = Vec::new();
+                for document in documents {
+                    let object: serde_json::Map<String, Value> = columns
+                        .iter()
+                        .map(|column| column.name.to_string())
+                        .zip(row(document, columns))
+                        .collect();
+                    serde_json::to_writer(&mut lines, &object).map_err(|e| e.to_string())?;
+                    lines.push(b'\n');
+                }
+                Ok(Bytes::from(lines))
+            }
+            Encoder::Parquet {
+                name,
+                columns,
+                writer,
+            } => {
+                let rows: Vec<Vec<Value>> = documents
+                    .into_iter()
+                    .map(|document| row(document, columns))
+                    .collect();
+                if writer.is_none() {
+                    *writer = Some(Box::new(parquet_writer(name, columns)?));
+                }
+                let writer = writer.as_mut().expect("writer was just created");
+                if !rows.is_empty() {
+                    write_row_group(writer, columns, &rows).map_err(|e| e.to_string())?;
+                }
+                Ok(Bytes::from(std::mem::take(writer.inner_mut())))
+            }
+        }
+    }
+
+    // Whatever has to follow the last chunk, i.e. the Parquet footer
+    pub fn finish(self) -> Result<Bytes, String> {
+        match self {
+            Encoder::Parquet {
+                name,
+                columns,
+                writer,
+            } => {
+                let writer = match writer {
+                    Some(writer) => *writer,
+                    None => parquet_writer(name, &columns)?,
+                };
+                writer
+                    .into_inner()
+                    .map(Bytes::from)
+                    .map_err(|e| e.to_string())
+            }
+            _ => Ok(Bytes::new()),
+        }
+    }
+}
+
+fn parquet_writer(name: &str, columns: &[Column]) -> Result<ParquetWriter, String> {
+    let message = format!(
+        "message {} {{ {} }}",
+        name,
+        columns
+            .iter()
+            .map(|column| format!("OPTIONAL {} {};", column.kind.parquet_type(), column.name))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    let schema = parse_message_type(&message).map_err(|e| e.to_string())?;
+    SerializedFileWriter::new(
+        Vec::new(),
+        Arc::new(schema),
+        Arc::new(WriterProperties::builder().build()),
+    )
+    .map_err(|e| e.to_string())
+}
+
+fn write_row_group(
+    writer: &mut ParquetWriter,
+    columns: &[Column],
+    rows: &[Vec<Value>],
+) -> parquet::errors::Result<()> {
+    let mut row_group = writer.next_row_group()?;
+    let mut index = 0;
+    while let Some(mut column_writer) = row_group.next_column()? {
+        let values: Vec<&Value> = rows.iter().map(|row| &row[index]).collect();
+        let levels: Vec<i16> = values.iter().map(|value| !value.is_null() as i16).collect();
+        let present = values.iter().filter(|value| !value.is_null());
+        match columns[index].kind {
+            ColumnType::Int64 => {
+                let values: Vec<i64> = present
+                    .map(|value| value.as_f64().unwrap_or_default() as i64)
+                    .collect();
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            ColumnType::Double => {
+                let values: Vec<f64> = present
+                    .map(|value| value.as_f64().unwrap_or_default())
+                    .collect();
+                column_writer
+                    .typed::<DoubleType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            ColumnType::Text => {
+                let values: Vec<ByteArray> = present
+                    .map(|value| ByteArray::from(cell(value).as_str()))
+                    .collect();
+                column_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+        }
+        column_writer.close()?;
+        index += 1;
+    }
+    row_group.close()?;
+    Ok(())
+}
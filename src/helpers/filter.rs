@@ -0,0 +1,116 @@
+use mongodb::bson::{doc, Bson, Document};
+use std::str::FromStr;
+
+use crate::helpers::api_error::ApiError;
+
+// Comparison operators of `filter=`, two character ones first so `>=` isn't read as `>`
+const OPERATORS: [(&str, &str); 7] = [
+    (">=", "$gte"),
+    ("<=", "$lte"),
+    ("!=", "$ne"),
+    ("==", "$eq"),
+    (">", "$gt"),
+    ("<", "$lt"),
+    ("=", "$eq"),
+];
+
+// One `field<op>value` term of `filter=`, e.g. `totalVolumeUSD>1e6`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub field: String,
+    pub operator: &'static str,
+    pub value: f64,
+}
+
+impl FromStr for Condition {
+    type Err = ApiError;
+
+    fn from_str(term: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            ApiError::invalid_parameter(
+                "filter",
+                format!(
+                    "Invalid filter '{}', expected a field, one of >, >=, <, <=, =, != and a number.",
+                    term
+                ),
+            )
+        };
+        let (position, symbol, operator) = term
+            .char_indices()
+            .find_map(|(position, _)| {
+                OPERATORS
+                    .iter()
+                    .find(|(symbol, _)| term[position..].starts_with(symbol))
+                    .map(|(symbol, operator)| (position, *symbol, *operator))
+            })
+            .ok_or_else(invalid)?;
+        let field = term[..position].trim();
+        let value = term[position + symbol.len()..]
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| invalid())?;
+        if field.is_empty() || !value.is_finite() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            field: field.to_string(),
+            operator,
+            value,
+        })
+    }
+}
+
+// Adds `field <operator> value` to a `$match` document. Bounds on a field that already has
+// one are combined with it, keeping the tighter of two bounds in the same direction, and
+// values a field must not equal are gathered in `$nin`.
+pub fn merge_condition(
+    filter: &mut Document,
+    field: &str,
+    operator: &'static str,
+    value: f64,
+) -> Result<(), ApiError> {
+    let mut operators = match filter.remove(field) {
+        Some(Bson::Document(operators)) => operators,
+        Some(existing) => doc! { "$eq": existing },
+        None => Document::new(),
+    };
+    if operator == "$ne" {
+        exclude(&mut operators, value);
+        filter.insert(field, operators);
+        return Ok(());
+    }
+    let merged = match (operators.get(operator).and_then(Bson::as_f64), operator) {
+        (None, _) => value,
+        (Some(existing), "$gt" | "$gte") => existing.max(value),
+        (Some(existing), "$lt" | "$lte") => existing.min(value),
+        (Some(existing), _) if existing == value => value,
+        (Some(_), _) => {
+            return Err(ApiError::invalid_parameter(
+                "filter",
+                format!("Conflicting conditions on '{}'.", field),
+            ))
+        }
+    };
+    operators.insert(operator, merged);
+    filter.insert(field, operators);
+    Ok(())
+}
+
+// Adds `value` to the values excluded by `$ne`, which becomes `$nin` once there are several
+fn exclude(operators: &mut Document, value: f64) {
+    let mut excluded: Vec<Bson> = match operators.remove("$nin") {
+        Some(Bson::Array(values)) => values,
+        _ => Vec::new(),
+    };
+    excluded.extend(operators.remove("$ne"));
+    if !excluded
+        .iter()
+        .any(|excluded| excluded.as_f64() == Some(value))
+    {
+        excluded.push(Bson::Double(value));
+    }
+    match <[Bson; 1]>::try_from(excluded) {
+        Ok([single]) => operators.insert("$ne", single),
+        Err(excluded) => operators.insert("$nin", excluded),
+    };
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+
+pub const HOUR: i64 = 3600;
+// Midgard returns at most 400 intervals per request
+pub const MAX_PAGE_HOURS: i64 = 400;
+
+// Half-open range of unix seconds, `[from, to)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub from: i64,
+    pub to: i64,
+}
+
+impl TimeRange {
+    pub fn hours(&self) -> i64 {
+        (self.to - self.from) / HOUR
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}) ({}h)", self.from, self.to, self.hours())
+    }
+}
+
+pub fn align_to_hour(timestamp: i64) -> i64 {
+    timestamp - timestamp.rem_euclid(HOUR)
+}
+
+// Returns the hourly buckets in `[from, to)` whose start time is not in `existing`,
+// merged into contiguous ranges
+pub fn missing_ranges(existing: &[i64], from: i64, to: i64) -> Vec<TimeRange> {
+    let from = align_to_hour(from);
+    let to = align_to_hour(to);
+    let mut existing: Vec<i64> = existing
+        .iter()
+        .copied()
+        .filter(|start| *start >= from && *start < to)
+        .collect();
+    existing.sort_unstable();
+    existing.dedup();
+
+    let mut gaps = Vec::new();
+    let mut cursor = from;
+    for start in existing {
+        if start > cursor {
+            gaps.push(TimeRange {
+                from: cursor,
+                to: align_to_hour(start),
+            });
+        }
+        cursor = cursor.max(align_to_hour(start) + HOUR);
+    }
+    if cursor < to {
+        gaps.push(TimeRange { from: cursor, to });
+    }
+    gaps
+}
+
+// Splits a range into consecutive windows of at most `MAX_PAGE_HOURS` hours
+pub fn hourly_pages(from: i64, to: i64) -> Vec<TimeRange> {
+    let mut pages = Vec::new();
+    let mut page_from = from;
+    while page_from < to {
+        let page_to = (page_from + MAX_PAGE_HOURS * HOUR).min(to);
+        pages.push(TimeRange {
+            from: page_from,
+            to: page_to,
+        });
+        page_from = page_to;
+    }
+    pages
+}
@@ -0,0 +1,77 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    Error,
+};
+use std::time::Instant;
+use tracing::{error, info, info_span, Instrument};
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+use crate::config::{LogFormat, LoggingConfig};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+// Installs the global subscriber; JSON lines carry the fields of every enclosing span, so an
+// aggregation logged while serving a request has that request's id
+pub fn init(config: &LoggingConfig) {
+    let filter = EnvFilter::try_new(&config.level).unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match config.format {
+        LogFormat::Json => builder
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .init(),
+        LogFormat::Text => builder.init(),
+    }
+}
+
+// The caller's `X-Request-Id` when it is short printable text, a new UUID otherwise
+fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= 128)
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+// Serves each request inside a `request` span holding its id, and returns the id in
+// `X-Request-Id` so a client report can be matched with the logs
+pub async fn trace_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = request_id(&req);
+    let span = info_span!(
+        "request",
+        request_id = %request_id,
+        method = %req.method(),
+        path = %req.path(),
+    );
+    let started = Instant::now();
+    let result = next.call(req).instrument(span.clone()).await;
+    let duration_ms = started.elapsed().as_millis() as u64;
+
+    let _entered = span.enter();
+    match result {
+        Ok(mut res) => {
+            info!(
+                status = res.status().as_u16(),
+                duration_ms, "Request finished"
+            );
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+            Ok(res)
+        }
+        Err(e) => {
+            error!(error = %e, duration_ms, "Request failed");
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    Error,
+};
+use prometheus::{
+    CounterVec, Encoder, GaugeVec, HistogramOpts, HistogramVec, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use crate::models::dataset::Dataset;
+
+// Every series the API exposes on `/metrics`. Series of a dataset are labelled with its
+// name rather than its collection, which can be renamed in the config.
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to answer API requests",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap(),
+    )
+});
+
+static MONGO_AGGREGATION_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "mongo_aggregation_duration_seconds",
+                "Time taken by bucket aggregations",
+            ),
+            &["dataset"],
+        )
+        .unwrap(),
+    )
+});
+
+static MIDGARD_REQUESTS: LazyLock<CounterVec> = LazyLock::new(|| {
+    register(
+        CounterVec::new(
+            Opts::new("midgard_requests_total", "Requests sent to Midgard"),
+            &["endpoint"],
+        )
+        .unwrap(),
+    )
+});
+
+static MIDGARD_FAILURES: LazyLock<CounterVec> = LazyLock::new(|| {
+    register(
+        CounterVec::new(
+            Opts::new(
+                "midgard_request_failures_total",
+                "Midgard requests that failed or returned an error status",
+            ),
+            &["endpoint"],
+        )
+        .unwrap(),
+    )
+});
+
+static INGESTED_DOCUMENTS: LazyLock<CounterVec> = LazyLock::new(|| {
+    register(
+        CounterVec::new(
+            Opts::new(
+                "ingested_documents_total",
+                "New intervals stored from Midgard",
+            ),
+            &["dataset"],
+        )
+        .unwrap(),
+    )
+});
+
+static LAST_SUCCESSFUL_SYNC: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register(
+        GaugeVec::new(
+            Opts::new(
+                "last_successful_sync_timestamp_seconds",
+                "Unix time of the last sync that left no gaps behind",
+            ),
+            &["dataset"],
+        )
+        .unwrap(),
+    )
+});
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(metric: T) -> T {
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("Metric registered twice");
+    metric
+}
+
+// The registry in the Prometheus text format
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("Unable to encode metrics");
+    String::from_utf8(buffer).expect("Metrics are not valid UTF-8")
+}
+
+// Times every request, labelled with the route pattern rather than the path, so unknown
+// paths all land in one `unmatched` series
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .match_pattern()
+        .unwrap_or_else(|| String::from("unmatched"));
+    let result = next.call(req).await;
+    let status = match &result {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[&method, &route, status.as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    result
+}
+
+pub fn observe_aggregation(dataset: Dataset, started: Instant) {
+    MONGO_AGGREGATION_DURATION
+        .with_label_values(&[dataset.name()])
+        .observe(started.elapsed().as_secs_f64());
+}
+
+pub fn count_midgard_request(endpoint: &str, failed: bool) {
+    MIDGARD_REQUESTS.with_label_values(&[endpoint]).inc();
+    if failed {
+        MIDGARD_FAILURES.with_label_values(&[endpoint]).inc();
+    }
+}
+
+pub fn count_ingested(dataset: Dataset, inserted: u64) {
+    INGESTED_DOCUMENTS
+        .with_label_values(&[dataset.name()])
+        .inc_by(inserted as f64);
+}
+
+pub fn mark_synced(dataset: Dataset, timestamp: i64) {
+    LAST_SUCCESSFUL_SYNC
+        .with_label_values(&[dataset.name()])
+        .set(timestamp as f64);
+}
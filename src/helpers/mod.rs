@@ -1,4 +1,13 @@
+pub mod api_error;
 pub mod cron;
+pub mod cursor;
+pub mod export;
+pub mod filter;
+pub mod gaps;
+pub mod logging;
+pub mod metrics;
 pub mod query_parser;
+pub mod shutdown;
+pub mod sort;
 pub mod time_formatter;
 pub mod time_intervals;
@@ -1,7 +1,16 @@
-use crate::{helpers::time_formatter::parse_date, routes::types::CommonQueryParams};
-use actix_web::HttpResponse;
+use crate::{
+    db::store::Rollup,
+    helpers::{
+        api_error::ApiError,
+        cursor::Cursor,
+        filter::{merge_condition, Condition},
+        sort::SortKey,
+        time_formatter::parse_date,
+    },
+    routes::types::CommonQueryParams,
+};
 use chrono::Utc;
-use mongodb::bson::doc;
+use mongodb::bson::{doc, Document};
 
 #[derive(Debug)]
 pub struct QueryParser {
@@ -9,26 +18,40 @@ pub struct QueryParser {
     pub count: i64,
     pub from: i64,
     pub to: i64,
+    // Continues after this bucket instead of skipping `page - 1` pages
+    pub cursor: Option<Cursor>,
+    // Fields picked with `fields=`, `None` to return all of them
+    pub fields: Option<Vec<String>>,
+    // Conditions of `filter=`, applied with the date range
+    pub filters: Vec<Condition>,
 }
 impl QueryParser {
-    pub fn new(query: &CommonQueryParams, max_count: i64) -> Result<Self, HttpResponse> {
+    // `max_count` caps and defaults `count`, `default_from` is the start of the range when
+    // `from` is missing
+    pub fn new(
+        query: &CommonQueryParams,
+        max_count: i64,
+        default_from: i64,
+    ) -> Result<Self, ApiError> {
         let count = query
             .count
             .as_ref()
             .map(|c| c.parse::<i64>())
             .transpose()
-            .map_err(|_| HttpResponse::BadRequest().body("Count must be a valid number."))?
+            .map_err(|_| ApiError::invalid_parameter("count", "Count must be a valid number."))?
             .unwrap_or(max_count);
 
         if count < 1 {
-            return Err(
-                HttpResponse::BadRequest().body("Count must be greater than or equal to 1.")
-            );
+            return Err(ApiError::invalid_parameter(
+                "count",
+                "Count must be greater than or equal to 1.",
+            ));
         }
         if count > max_count {
-            return Err(
-                HttpResponse::BadRequest().body(format!("Count must not exceed {}.", max_count))
-            );
+            return Err(ApiError::invalid_parameter(
+                "count",
+                format!("Count must not exceed {}.", max_count),
+            ));
         }
 
         let page = query
@@ -36,45 +59,154 @@ impl QueryParser {
             .as_ref()
             .map(|p| p.parse::<i64>())
             .transpose()
-            .map_err(|_| HttpResponse::BadRequest().body("Page must be a valid number."))?
+            .map_err(|_| ApiError::invalid_parameter("page", "Page must be a valid number."))?
             .unwrap_or(1);
 
         if page < 1 {
-            return Err(HttpResponse::BadRequest().body("Page must be greater than or equal to 1."));
+            return Err(ApiError::invalid_parameter(
+                "page",
+                "Page must be greater than or equal to 1.",
+            ));
         }
 
         let from = if let Some(from_str) = &query.from {
-            parse_date(from_str)?
+            parse_date(from_str).map_err(|e| e.with_field("from"))?
         } else {
-            1648771200 // Default to April 1, 2022
+            default_from
         };
 
         let to = if let Some(to_str) = &query.to {
-            parse_date(to_str)?
+            parse_date(to_str).map_err(|e| e.with_field("to"))?
         } else {
             Utc::now().timestamp() // Default to current time
         };
 
         if from > to {
-            return Err(HttpResponse::BadRequest().body("'from' cannot be greater than 'to'."));
+            return Err(ApiError::invalid_parameter(
+                "from",
+                "'from' cannot be greater than 'to'.",
+            ));
         }
 
+        let cursor = query.cursor.as_deref().map(Cursor::decode).transpose()?;
+
+        let fields = query.fields.as_ref().map(|fields| {
+            fields
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(String::from)
+                .collect()
+        });
+
+        let filters = query
+            .filter
+            .iter()
+            .flat_map(|filter| filter.split(','))
+            .filter(|term| !term.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+
         Ok(Self {
             page,
             count,
             from,
             to,
+            cursor,
+            fields,
+            filters,
         })
     }
 
     pub fn skip(&self) -> i64 {
-        (self.page - 1).max(0) * self.count
+        if self.cursor.is_some() {
+            return 0;
+        }
+        (self.page - 1).max(0).saturating_mul(self.count)
+    }
+
+    // The cursor to continue from, once checked against the requested sort
+    pub fn after(&self, sort: &[SortKey]) -> Result<Option<Cursor>, ApiError> {
+        if let Some(cursor) = &self.cursor {
+            cursor.check_sort(sort)?;
+        }
+        Ok(self.cursor.clone())
+    }
+
+    pub fn check_fields(&self, field_names: &[&str]) -> Result<(), ApiError> {
+        for field in self.fields.iter().flatten() {
+            if !field_names.contains(&field.as_str()) {
+                return Err(ApiError::invalid_parameter(
+                    "fields",
+                    format!("Invalid field '{}'.", field),
+                ));
+            }
+        }
+        Ok(())
     }
 
-    pub fn date_filter(&self) -> mongodb::bson::Document {
+    pub fn check_filters(&self, field_names: &[&str]) -> Result<(), ApiError> {
+        for condition in &self.filters {
+            if !field_names.contains(&condition.field.as_str()) {
+                return Err(ApiError::invalid_parameter(
+                    "filter",
+                    format!("Invalid filter field '{}'.", condition.field),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Whether a field is returned: the sort keys always are, they position the cursor, and
+    // so are the filtered fields, which the buckets are matched on
+    pub fn keeps(&self, field: &str, sort: &[SortKey]) -> bool {
+        match &self.fields {
+            Some(fields) => {
+                sort.iter().any(|key| key.field == field)
+                    || self
+                        .filters
+                        .iter()
+                        .any(|condition| condition.field == field)
+                    || fields.iter().any(|f| f == field)
+            }
+            None => true,
+        }
+    }
+
+    // The part of a rollup that computes the returned fields
+    pub fn project(&self, rollup: Vec<Rollup>, sort: &[SortKey]) -> Vec<Rollup> {
+        rollup
+            .into_iter()
+            .filter(|rollup| self.keeps(rollup.name, sort))
+            .collect()
+    }
+
+    // Columns of an export, in the model's order
+    pub fn columns(&self, field_names: Vec<&'static str>, sort: &[SortKey]) -> Vec<&'static str> {
+        field_names
+            .into_iter()
+            .filter(|field| self.keeps(field, sort))
+            .collect()
+    }
+
+    pub fn date_filter(&self) -> Document {
         doc! {
             "startTime": { "$gte": self.from as f64 },
             "endTime": { "$lte": self.to as f64 }
         }
     }
+
+    // The `filter=` conditions, which apply to the buckets rather than their hours
+    pub fn conditions(&self) -> Result<Document, ApiError> {
+        let mut conditions = Document::new();
+        for condition in &self.filters {
+            merge_condition(
+                &mut conditions,
+                &condition.field,
+                condition.operator,
+                condition.value,
+            )?;
+        }
+        Ok(conditions)
+    }
 }
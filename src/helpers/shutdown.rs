@@ -0,0 +1,70 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+// Shared by the HTTP server and the ingestion tasks. Ingestion checks it between ranges, so
+// a range that is being stored finishes and its checkpoint is saved before the process exits.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+#[derive(Clone)]
+pub struct ShutdownTrigger {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Shutdown {
+    pub fn new() -> (ShutdownTrigger, Self) {
+        let (sender, receiver) = watch::channel(false);
+        let sender = Arc::new(sender);
+        (ShutdownTrigger { sender }, Self { receiver })
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    // Resolves once shutdown is requested, or straight away if it already was
+    pub async fn requested(&mut self) {
+        // An error means the trigger is gone, which only happens while the process exits
+        let _ = self.receiver.wait_for(|requested| *requested).await;
+    }
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+}
+
+// Waits for Ctrl-C or, on Unix, SIGTERM
+pub async fn wait_for_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!(error = %e, "Unable to listen for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!(error = %e, "Unable to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received Ctrl-C, shutting down"),
+        _ = terminate => info!("Received SIGTERM, shutting down"),
+    }
+}
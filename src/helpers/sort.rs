@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::api_error::ApiError;
+
+// One key of a bucket sort, `order` is 1 for ascending and -1 for descending
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SortKey {
+    pub field: String,
+    pub order: i32,
+}
+
+impl SortKey {
+    pub fn new(field: &str, order: i32) -> Self {
+        Self {
+            field: field.to_string(),
+            order,
+        }
+    }
+}
+
+// Sort keys of `sort=-totalVolumeUSD,startTime`, or of `sort_by` and `order` when `sort` is
+// missing, checked against the fields of the model. Newest first by default. `startTime`
+// is appended when it isn't a key, so every bucket has a fixed position for cursors.
+pub fn parse_sort(
+    sort: Option<&str>,
+    sort_by: Option<&str>,
+    order: Option<&str>,
+    field_names: &[&str],
+) -> Result<Vec<SortKey>, ApiError> {
+    let mut keys = Vec::new();
+    match sort {
+        Some(sort) => {
+            for term in sort
+                .split(',')
+                .map(str::trim)
+                .filter(|term| !term.is_empty())
+            {
+                let (field, order) = match term.strip_prefix('-') {
+                    Some(field) => (field, -1),
+                    None => (term.trim_start_matches('+'), 1),
+                };
+                if !field_names.contains(&field) {
+                    return Err(ApiError::invalid_parameter(
+                        "sort",
+                        format!("Invalid sort field '{}'.", field),
+                    ));
+                }
+                if keys.iter().any(|key: &SortKey| key.field == field) {
+                    return Err(ApiError::invalid_parameter(
+                        "sort",
+                        format!("'{}' is sorted on more than once.", field),
+                    ));
+                }
+                keys.push(SortKey::new(field, order));
+            }
+            if keys.is_empty() {
+                return Err(ApiError::invalid_parameter(
+                    "sort",
+                    "Sort needs at least one field.",
+                ));
+            }
+        }
+        None => {
+            let sort_by = sort_by.unwrap_or("startTime");
+            if !field_names.contains(&sort_by) {
+                return Err(ApiError::invalid_parameter(
+                    "sort_by",
+                    "Invalid sort_by parameter.",
+                ));
+            }
+            let order = match order {
+                Some("asc") => 1,
+                _ => -1,
+            };
+            keys.push(SortKey::new(sort_by, order));
+        }
+    }
+
+    if !keys.iter().any(|key| key.field == "startTime") {
+        let order = keys[0].order;
+        keys.push(SortKey::new("startTime", order));
+    }
+    Ok(keys)
+}
@@ -1,11 +1,30 @@
-use actix_web::HttpResponse;
-use chrono::NaiveDateTime;
+use actix_web::http::StatusCode;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
 
-pub fn parse_date(date_str: &str) -> Result<i64, HttpResponse> {
+use crate::helpers::api_error::ApiError;
+
+pub fn parse_date(date_str: &str) -> Result<i64, ApiError> {
     match NaiveDateTime::parse_from_str(date_str, "%Y-%m-%dT%H:%M:%S") {
         Ok(datetime) => Ok(datetime.and_utc().timestamp()),
-        Err(_) => {
-            Err(HttpResponse::BadRequest().body("Invalid date format. Use 'YYYY-MM-DDTHH:MM:SS'."))
-        }
+        Err(_) => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_parameter",
+            "Invalid date format. Use 'YYYY-MM-DDTHH:MM:SS'.",
+        )),
+    }
+}
+
+// Accepts either a bare date (midnight UTC) or the same datetime format as the API
+pub fn parse_cli_date(date_str: &str) -> Result<i64, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc().timestamp());
     }
+    NaiveDateTime::parse_from_str(date_str, "%Y-%m-%dT%H:%M:%S")
+        .map(|datetime| datetime.and_utc().timestamp())
+        .map_err(|_| {
+            format!(
+                "Invalid date '{}'. Use 'YYYY-MM-DD' or 'YYYY-MM-DDTHH:MM:SS'.",
+                date_str
+            )
+        })
 }
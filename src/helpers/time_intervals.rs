@@ -1,12 +1,133 @@
-// Helper function to convert the interval string into seconds
-pub fn interval_to_seconds(interval: &str) -> i64 {
-    match interval {
-        "hour" => 3600,
-        "day" => 86400,
-        "week" => 604800,
-        "month" => 2629800,
-        "quarter" => 7889400,
-        "year" => 31557600,
-        _ => 86400,
+use crate::helpers::api_error::ApiError;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+// Bucket sizes accepted by the `interval` query parameter. Everything above an hour
+// follows the calendar of the requested timezone; weeks start on Monday (ISO 8601).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Interval {
+    pub const ALL: [Interval; 6] = [
+        Interval::Hour,
+        Interval::Day,
+        Interval::Week,
+        Interval::Month,
+        Interval::Quarter,
+        Interval::Year,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Interval::Hour => "hour",
+            Interval::Day => "day",
+            Interval::Week => "week",
+            Interval::Month => "month",
+            Interval::Quarter => "quarter",
+            Interval::Year => "year",
+        }
+    }
+}
+
+impl FromStr for Interval {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Interval::ALL
+            .into_iter()
+            .find(|interval| interval.name() == value)
+            .ok_or_else(|| {
+                format!(
+                    "Invalid interval '{}', expected one of hour, day, week, month, quarter, year.",
+                    value
+                )
+            })
     }
 }
+
+// How the hourly intervals of a request are grouped: bucket size plus the timezone
+// whose calendar the buckets follow
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucketing {
+    pub interval: Interval,
+    pub timezone: Tz,
+}
+
+impl Bucketing {
+    // Parses the `interval` and `tz` query parameters, defaulting to hourly buckets in UTC
+    pub fn parse(interval: Option<&str>, tz: Option<&str>) -> Result<Self, ApiError> {
+        let interval = interval
+            .unwrap_or("hour")
+            .parse::<Interval>()
+            .map_err(|e| ApiError::invalid_parameter("interval", e))?;
+        let timezone = tz
+            .unwrap_or("UTC")
+            .parse::<Tz>()
+            .map_err(|_| ApiError::invalid_parameter("tz", "Invalid tz parameter."))?;
+        Ok(Self { interval, timezone })
+    }
+}
+
+fn first_day(year: i32, month: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, 1).expect("month is between 1 and 12")
+}
+
+// Start of the bucket containing `timestamp`, mirroring `$dateTrunc` with Monday weeks
+pub fn bucket_start(bucketing: &Bucketing, timestamp: i64) -> i64 {
+    let timezone = bucketing.timezone;
+    let Some(local) = timezone.timestamp_opt(timestamp, 0).single() else {
+        return timestamp;
+    };
+    let date = local.date_naive();
+    let start: NaiveDateTime = match bucketing.interval {
+        Interval::Hour => {
+            return local
+                .with_minute(0)
+                .and_then(|hour| hour.with_second(0))
+                .map_or(timestamp, |hour| hour.timestamp())
+        }
+        Interval::Day => date.into(),
+        Interval::Week => {
+            (date - Duration::days(date.weekday().num_days_from_monday() as i64)).into()
+        }
+        Interval::Month => first_day(date.year(), date.month()).into(),
+        Interval::Quarter => first_day(date.year(), date.month0() / 3 * 3 + 1).into(),
+        Interval::Year => first_day(date.year(), 1).into(),
+    };
+    // A midnight skipped by a DST change starts the bucket at the first hour that exists
+    timezone
+        .from_local_datetime(&start)
+        .earliest()
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(start + Duration::hours(1)))
+                .earliest()
+        })
+        .map_or(timestamp, |start| start.timestamp())
+}
+
+// Start of the bucket after the one containing `timestamp`. Stepping by the longest a
+// bucket can last, DST hour included, always lands in the next bucket, as two buckets in
+// a row are longer than that.
+pub fn next_bucket_start(bucketing: &Bucketing, timestamp: i64) -> i64 {
+    let longest = match bucketing.interval {
+        Interval::Hour => Duration::hours(1),
+        Interval::Day => Duration::hours(25),
+        Interval::Week => Duration::days(7) + Duration::hours(1),
+        Interval::Month => Duration::days(31) + Duration::hours(1),
+        Interval::Quarter => Duration::days(92) + Duration::hours(1),
+        Interval::Year => Duration::days(366) + Duration::hours(1),
+    };
+    bucket_start(
+        bucketing,
+        bucket_start(bucketing, timestamp) + longest.num_seconds(),
+    )
+}
@@ -1,13 +1,29 @@
 #![recursion_limit = "256"]
+mod cli;
+mod config;
 mod db;
 mod helpers;
+mod midgard;
 mod models;
 mod routes;
 mod services;
+#[cfg(test)]
 mod tests;
-use crate::helpers::cron::start_scheduler;
-use actix_web::{get, web::Data, App, HttpResponse, HttpServer, Responder};
-use db::connection::MongoDB;
+use crate::helpers::api_error::query_config;
+use crate::helpers::cron::{pull_latest_data, start_scheduler};
+use crate::helpers::logging::{self, trace_requests};
+use crate::helpers::metrics::track_requests;
+use crate::helpers::shutdown::{wait_for_signal, Shutdown, ShutdownTrigger};
+use crate::services::backfill_service::run_backfill;
+use actix_web::{get, middleware::from_fn, web::Data, App, HttpResponse, HttpServer, Responder};
+use clap::Parser;
+use cli::{Cli, Command};
+use config::Config;
+use db::{connection::MongoDB, store::HistoryStore};
+use midgard::client::MidgardClient;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
 #[get("/")]
 async fn home() -> impl Responder {
     HttpResponse::Ok().body("Rust Backend Server")
@@ -15,29 +31,131 @@ async fn home() -> impl Responder {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let mongo_db: MongoDB = MongoDB::init().await.expect("Error connecting to Database");
-    println!("Connected to Database");
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref()).map_err(std::io::Error::other)?;
+    logging::init(&config.logging);
 
-    // Start the scheduler for updating data
-    let mongo_db_clone = mongo_db.clone();
+    // Ctrl-C and SIGTERM stop the server, the scheduler and backfills the same way
+    let (trigger, mut shutdown) = Shutdown::new();
+    let signal_trigger = trigger.clone();
     tokio::spawn(async move {
-        if let Err(e) = start_scheduler(mongo_db_clone).await {
-            eprintln!("Error starting scheduler: {}", e);
+        wait_for_signal().await;
+        signal_trigger.trigger();
+    });
+
+    let mongo_db: MongoDB = tokio::select! {
+        connected = MongoDB::connect(&config.database) => connected.map_err(|e| {
+            std::io::Error::other(format!("Error connecting to Database: {}", e))
+        })?,
+        _ = shutdown.requested() => return Ok(()),
+    };
+    info!("Connected to Database");
+    let store: Arc<dyn HistoryStore> = Arc::new(mongo_db);
+    let midgard = MidgardClient::from_config(&config.midgard).with_shutdown(shutdown.clone());
+    info!(url = midgard.base_url(), "Using Midgard");
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config, store, midgard, trigger, shutdown).await,
+        Command::Backfill {
+            collection,
+            from,
+            to,
+            pool,
+        } => {
+            let inserted = run_backfill(
+                store.as_ref(),
+                &midgard,
+                collection,
+                pool.as_deref(),
+                from,
+                to,
+                &shutdown,
+            )
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+            info!(inserted, "Backfill finished");
+            Ok(())
+        }
+        Command::SyncOnce => pull_latest_data(
+            store.as_ref(),
+            &midgard,
+            config.scheduler.lookback_hours,
+            &shutdown,
+        )
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string())),
+    }
+}
+
+async fn serve(
+    config: Config,
+    store: Arc<dyn HistoryStore>,
+    midgard: MidgardClient,
+    trigger: ShutdownTrigger,
+    shutdown: Shutdown,
+) -> std::io::Result<()> {
+    // Start the scheduler for updating data
+    let scheduler_store = store.clone();
+    let scheduler_config = config.scheduler.clone();
+    let scheduler_shutdown = shutdown.clone();
+    let scheduler = tokio::spawn(async move {
+        let result = start_scheduler(
+            scheduler_store,
+            midgard,
+            scheduler_config,
+            scheduler_shutdown,
+        )
+        .await;
+        if let Err(e) = result {
+            error!(error = %e, "Error starting scheduler");
         }
     });
 
-    let mongo_db: Data<MongoDB> = Data::new(mongo_db);
+    let store: Data<dyn HistoryStore> = Data::from(store);
+    let api_config = Data::new(config.api.clone());
+    let health_config = Data::new(config.health.clone());
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
-            .app_data(mongo_db.clone())
+            .app_data(store.clone())
+            .app_data(api_config.clone())
+            .app_data(health_config.clone())
+            .app_data(query_config())
+            .wrap(from_fn(track_requests))
+            .wrap(from_fn(trace_requests))
             .service(home)
             .configure(routes::depths_history::init)
             .configure(routes::earnings_history::init)
             .configure(routes::swaps_history::init)
             .configure(routes::rpmuh_history::init)
+            .configure(routes::candles::init)
+            .configure(routes::openapi::init)
+            .configure(routes::metrics::init)
+            .configure(routes::health::init)
+            .configure(routes::admin::init)
     })
-    .bind(("0.0.0.0", 3000))?
-    .run()
-    .await
+    .bind((config.server.host.as_str(), config.server.port))?
+    // Signals go through `shutdown`, so the scheduler stops together with the server
+    .disable_signals()
+    .shutdown_timeout(config.server.shutdown_timeout_secs)
+    .run();
+
+    let handle = server.handle();
+    let mut stop = shutdown;
+    tokio::spawn(async move {
+        stop.requested().await;
+        handle.stop(true).await;
+    });
+    let result = server.await;
+
+    // The server may also have stopped on its own, the scheduler stops either way
+    trigger.trigger();
+    let timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    if tokio::time::timeout(timeout, scheduler).await.is_err() {
+        warn!(
+            timeout_secs = timeout.as_secs(),
+            "Ingestion did not stop in time, exiting"
+        );
+    }
+    result
 }
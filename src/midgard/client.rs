@@ -0,0 +1,289 @@
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tracing::{debug, info, info_span, warn, Instrument};
+
+use crate::config::MidgardConfig;
+use crate::helpers::{metrics::count_midgard_request, shutdown::Shutdown};
+use crate::midgard::error::MidgardError;
+use crate::midgard::retry::{retry_after, RequestBudget, RetryPolicy};
+use crate::models::{
+    depth_history_model::DepthHistoryResponse, earning_history_model::EarningHistoryResponse,
+    pool_model::PoolSummary, rptmuh_model::RpmuHistoryResponse,
+    swap_history_model::SwapHistoryResponse,
+};
+
+pub const DEFAULT_BASE_URL: &str = "https://midgard.ninerealms.com";
+
+// Hourly history window sent to the Midgard `/v2/history/*` endpoints
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    pub count: Option<i64>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+impl HistoryQuery {
+    pub fn to_pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![("interval", String::from("hour"))];
+        if let Some(count) = self.count {
+            pairs.push(("count", count.to_string()));
+        }
+        if let Some(from) = self.from {
+            pairs.push(("from", from.to_string()));
+        }
+        if let Some(to) = self.to {
+            pairs.push(("to", to.to_string()));
+        }
+        pairs
+    }
+}
+
+#[derive(Clone)]
+pub struct MidgardClient {
+    http: Client,
+    base_url: String,
+    host: String,
+    policy: RetryPolicy,
+    budget: Arc<RequestBudget>,
+    // Cuts the waits between attempts short, so a retry delay can't outlast the
+    // shutdown timeout
+    shutdown: Option<Shutdown>,
+}
+
+impl MidgardClient {
+    pub fn new(base_url: &str) -> Self {
+        let http = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .pool_idle_timeout(Duration::from_secs(90))
+            .build()
+            .expect("Unable to build HTTP client");
+        let base_url = base_url.trim_end_matches('/').to_string();
+        let host = reqwest::Url::parse(&base_url)
+            .ok()
+            .and_then(|url| url.host_str().map(String::from))
+            .unwrap_or_else(|| base_url.clone());
+        let policy = RetryPolicy::default();
+        Self {
+            http,
+            base_url,
+            host,
+            budget: Arc::new(RequestBudget::new(policy.min_request_interval)),
+            policy,
+            shutdown: None,
+        }
+    }
+
+    pub fn from_config(config: &MidgardConfig) -> Self {
+        Self::new(&config.url).with_retry_policy(RetryPolicy::from(config))
+    }
+
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.budget = Arc::new(RequestBudget::new(policy.min_request_interval));
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub async fn depth_history(
+        &self,
+        pool: &str,
+        query: &HistoryQuery,
+    ) -> Result<DepthHistoryResponse, MidgardError> {
+        self.get_json("depths", &format!("/v2/history/depths/{}", pool), query)
+            .await
+    }
+
+    // Pools currently open for swapping and liquidity
+    pub async fn available_pools(&self) -> Result<Vec<PoolSummary>, MidgardError> {
+        let url = format!("{}/v2/pools?status=available", self.base_url);
+        let pools: Vec<PoolSummary> = self
+            .fetch_json("pools", url, &HistoryQuery::default())
+            .await?;
+        Ok(pools
+            .into_iter()
+            .filter(PoolSummary::is_available)
+            .collect())
+    }
+
+    pub async fn earnings_history(
+        &self,
+        query: &HistoryQuery,
+    ) -> Result<EarningHistoryResponse, MidgardError> {
+        self.get_json("earnings", "/v2/history/earnings", query)
+            .await
+    }
+
+    pub async fn runepool_history(
+        &self,
+        query: &HistoryQuery,
+    ) -> Result<RpmuHistoryResponse, MidgardError> {
+        self.get_json("runepool", "/v2/history/runepool", query)
+            .await
+    }
+
+    pub async fn swaps_history(
+        &self,
+        query: &HistoryQuery,
+    ) -> Result<SwapHistoryResponse, MidgardError> {
+        self.get_json("swaps", "/v2/history/swaps", query).await
+    }
+
+    pub fn url_for(&self, path: &str, query: &HistoryQuery) -> String {
+        let params: Vec<String> = query
+            .to_pairs()
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        format!("{}{}?{}", self.base_url, path, params.join("&"))
+    }
+
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        path: &str,
+        query: &HistoryQuery,
+    ) -> Result<T, MidgardError> {
+        self.fetch_json(endpoint, self.url_for(path, query), query)
+            .await
+    }
+
+    // `endpoint` names the Midgard endpoint in the request metrics, without the pool. Every
+    // attempt runs in a `midgard_fetch` span with the URL and the window of `query`.
+    async fn fetch_json<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        url: String,
+        query: &HistoryQuery,
+    ) -> Result<T, MidgardError> {
+        let span = info_span!(
+            "midgard_fetch",
+            endpoint,
+            url = %url,
+            from = query.from,
+            to = query.to,
+        );
+        self.fetch_with_retries(endpoint, url)
+            .instrument(span)
+            .await
+    }
+
+    async fn fetch_with_retries<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        url: String,
+    ) -> Result<T, MidgardError> {
+        let mut attempt = 0;
+        let started = Instant::now();
+
+        loop {
+            if !self.wait(self.budget.acquire(&self.host)).await {
+                return Err(MidgardError::Cancelled { url });
+            }
+            debug!(attempt, "Fetching from Midgard");
+
+            let (error, server_delay) = match self.send(&url).await {
+                Ok(body) => {
+                    let decoded = serde_json::from_slice(&body)
+                        .map_err(|source| MidgardError::Decode { url, source });
+                    count_midgard_request(endpoint, decoded.is_err());
+                    info!(
+                        bytes = body.len(),
+                        attempts = attempt + 1,
+                        duration_ms = started.elapsed().as_millis() as u64,
+                        "Fetched from Midgard"
+                    );
+                    return decoded;
+                }
+                Err(failure) => {
+                    count_midgard_request(endpoint, true);
+                    failure
+                }
+            };
+
+            let retryable = match &error {
+                MidgardError::Request { .. } => true,
+                MidgardError::Status { status, .. } => RetryPolicy::is_retryable_status(*status),
+                MidgardError::Decode { .. } | MidgardError::Cancelled { .. } => false,
+            };
+            if !retryable || attempt >= self.policy.max_retries {
+                return Err(error);
+            }
+
+            let delay = match server_delay {
+                Some(server_delay) => {
+                    let delay = self.policy.server_delay(server_delay);
+                    self.budget.pause(&self.host, delay).await;
+                    delay
+                }
+                None => self.policy.backoff(attempt),
+            };
+            attempt += 1;
+            warn!(
+                error = %error,
+                delay_ms = delay.as_millis() as u64,
+                attempt,
+                max_retries = self.policy.max_retries,
+                "Midgard request failed, retrying"
+            );
+            if !self.wait(sleep(delay)).await {
+                return Err(MidgardError::Cancelled { url });
+            }
+        }
+    }
+
+    // Waits for `until`, returning false instead if shutdown is requested first
+    async fn wait(&self, until: impl Future<Output = ()>) -> bool {
+        let Some(shutdown) = &self.shutdown else {
+            until.await;
+            return true;
+        };
+        let mut shutdown = shutdown.clone();
+        tokio::select! {
+            _ = until => true,
+            _ = shutdown.requested() => false,
+        }
+    }
+
+    // Sends a single request, returning the body on success or the error together
+    // with any `Retry-After` delay the server asked for
+    async fn send(&self, url: &str) -> Result<Vec<u8>, (MidgardError, Option<Duration>)> {
+        let request_error = |source| {
+            (
+                MidgardError::Request {
+                    url: url.to_string(),
+                    source,
+                },
+                None,
+            )
+        };
+
+        let response = self.http.get(url).send().await.map_err(request_error)?;
+        let status = response.status();
+        let server_delay = retry_after(response.headers());
+        let body = response.bytes().await.map_err(request_error)?;
+
+        if !status.is_success() {
+            return Err((
+                MidgardError::Status {
+                    url: url.to_string(),
+                    status,
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                },
+                server_delay,
+            ));
+        }
+        Ok(body.to_vec())
+    }
+}
@@ -0,0 +1,55 @@
+use reqwest::StatusCode;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum MidgardError {
+    // The request never produced a response (DNS, connect, timeout, ...)
+    Request {
+        url: String,
+        source: reqwest::Error,
+    },
+    // Midgard answered with a non-success status code
+    Status {
+        url: String,
+        status: StatusCode,
+        body: String,
+    },
+    // The response body did not match the expected model
+    Decode {
+        url: String,
+        source: serde_json::Error,
+    },
+    // Shutdown was requested while waiting to send the request or to retry it
+    Cancelled {
+        url: String,
+    },
+}
+
+impl fmt::Display for MidgardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidgardError::Request { url, source } => {
+                write!(f, "Request to {} failed: {}", url, source)
+            }
+            MidgardError::Status { url, status, body } => {
+                write!(f, "Midgard returned {} for {}: {}", status, url, body)
+            }
+            MidgardError::Decode { url, source } => {
+                write!(f, "Failed to decode response from {}: {}", url, source)
+            }
+            MidgardError::Cancelled { url } => {
+                write!(f, "Shutting down, gave up on {}", url)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MidgardError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MidgardError::Request { source, .. } => Some(source),
+            MidgardError::Decode { source, .. } => Some(source),
+            MidgardError::Status { .. } | MidgardError::Cancelled { .. } => None,
+        }
+    }
+}
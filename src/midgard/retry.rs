@@ -0,0 +1,120 @@
+use chrono::{DateTime, Utc};
+use reqwest::{header::HeaderMap, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{sleep_until, Instant};
+
+use crate::config::MidgardConfig;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    // Retries after the first attempt, so a request is sent at most `max_retries + 1` times
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Minimum spacing between two requests to the same host
+    pub min_request_interval: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+            min_request_interval: Duration::from_secs(3),
+        }
+    }
+}
+
+impl From<&MidgardConfig> for RetryPolicy {
+    fn from(config: &MidgardConfig) -> Self {
+        Self {
+            max_retries: config.max_retries,
+            base_delay: Duration::from_millis(config.base_delay_ms),
+            max_delay: Duration::from_millis(config.max_delay_ms),
+            min_request_interval: Duration::from_millis(config.min_request_interval_ms),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Full-jitter exponential backoff: a random delay in [0, min(max_delay, base_delay * 2^attempt)]
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        jitter(ceiling)
+    }
+
+    // The wait a `Retry-After` asked for, capped at `max_delay` so one bad header can't hold
+    // ingestion for hours
+    pub fn server_delay(&self, requested: Duration) -> Duration {
+        requested.min(self.max_delay)
+    }
+
+    pub fn is_retryable_status(status: StatusCode) -> bool {
+        status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+    }
+}
+
+fn jitter(ceiling: Duration) -> Duration {
+    let ceiling_ms = ceiling.as_millis() as u64;
+    if ceiling_ms == 0 {
+        return Duration::ZERO;
+    }
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(Utc::now().timestamp_nanos_opt().unwrap_or_default() as u128);
+    Duration::from_millis(hasher.finish() % (ceiling_ms + 1))
+}
+
+// Parses a `Retry-After` header given either as delay-seconds or as an HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let delay = date.with_timezone(&Utc) - Utc::now();
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+// Hands out request slots per host so consecutive calls are spaced by `min_interval`
+pub struct RequestBudget {
+    min_interval: Duration,
+    next_slot: Mutex<HashMap<String, Instant>>,
+}
+
+impl RequestBudget {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            next_slot: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn acquire(&self, host: &str) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let now = Instant::now();
+            let slot = next_slot.get(host).copied().unwrap_or(now).max(now);
+            next_slot.insert(host.to_string(), slot + self.min_interval);
+            slot
+        };
+        sleep_until(slot).await;
+    }
+
+    // Holds back every request to `host` until `delay` has passed, e.g. after a 429
+    pub async fn pause(&self, host: &str, delay: Duration) {
+        let mut next_slot = self.next_slot.lock().await;
+        let resume_at = Instant::now() + delay;
+        let slot = next_slot.entry(host.to_string()).or_insert(resume_at);
+        if *slot < resume_at {
+            *slot = resume_at;
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::db::store::Rollup;
+use crate::models::dataset::Dataset;
+
+// Price fields candles can be drawn from, with the dataset each one is stored in
+pub const CANDLE_FIELDS: [(&str, Dataset); 3] = [
+    ("assetPrice", Dataset::Depths),
+    ("assetPriceUSD", Dataset::Depths),
+    ("runePriceUSD", Dataset::Swaps),
+];
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Candle {
+    pub start_time: f64,
+    pub end_time: f64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    // Swap volume of the network over the candle, in RUNE
+    #[serde(default)]
+    pub volume: f64,
+}
+
+impl Candle {
+    pub fn field_names() -> Vec<&'static str> {
+        vec![
+            "startTime",
+            "endTime",
+            "open",
+            "high",
+            "low",
+            "close",
+            "volume",
+        ]
+    }
+
+    // The price field and its dataset, if candles can be drawn from `field`
+    pub fn source(field: &str) -> Option<(&'static str, Dataset)> {
+        CANDLE_FIELDS
+            .into_iter()
+            .find(|(candle_field, _)| *candle_field == field)
+    }
+
+    // How the hourly prices of a bucket become one candle
+    pub fn rollup(field: &'static str) -> Vec<Rollup> {
+        vec![
+            Rollup::first("startTime"),
+            Rollup::last("endTime"),
+            Rollup::first(field).named("open"),
+            Rollup::max(field).named("high"),
+            Rollup::min(field).named("low"),
+            Rollup::last(field).named("close"),
+        ]
+    }
+
+    // Swap volume of the same buckets, matched to candles by their time span
+    pub fn volume_rollup() -> Vec<Rollup> {
+        vec![
+            Rollup::first("startTime"),
+            Rollup::last("endTime"),
+            Rollup::sum("totalVolume"),
+        ]
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+// Progress of a backfill run; `cursor` is the start of the first hour not yet stored
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncCheckpoint {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub dataset: String,
+    pub pool: Option<String>,
+    pub from: i64,
+    pub to: i64,
+    pub cursor: i64,
+    pub completed: bool,
+    pub updated_at: i64,
+}
+
+impl SyncCheckpoint {
+    // Runs over the same dataset and pool from the same start share a checkpoint, whatever
+    // their end, so a run without a fixed end can be resumed later
+    pub fn key(dataset: &str, pool: Option<&str>, from: i64) -> String {
+        format!("backfill:{}:{}:{}", dataset, pool.unwrap_or("-"), from)
+    }
+}
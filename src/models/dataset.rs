@@ -0,0 +1,64 @@
+use std::fmt;
+use std::str::FromStr;
+
+// The four hourly history collections ingested from Midgard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dataset {
+    Depths,
+    Earnings,
+    Members,
+    Swaps,
+}
+
+impl Dataset {
+    pub const ALL: [Dataset; 4] = [
+        Dataset::Depths,
+        Dataset::Earnings,
+        Dataset::Members,
+        Dataset::Swaps,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dataset::Depths => "depths",
+            Dataset::Earnings => "earnings",
+            Dataset::Members => "members",
+            Dataset::Swaps => "swaps",
+        }
+    }
+
+    pub fn collection_name(&self) -> &'static str {
+        match self {
+            Dataset::Depths => "depths_history",
+            Dataset::Earnings => "earnings_history",
+            Dataset::Members => "members_history",
+            Dataset::Swaps => "swaps_history",
+        }
+    }
+}
+
+impl fmt::Display for Dataset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Dataset {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // Midgard calls the members history "runepool"
+        if value == "runepool" {
+            return Ok(Dataset::Members);
+        }
+        Dataset::ALL
+            .into_iter()
+            .find(|dataset| value == dataset.name() || value == dataset.collection_name())
+            .ok_or_else(|| {
+                format!(
+                    "Unknown collection '{}', expected one of depths, earnings, members, swaps",
+                    value
+                )
+            })
+    }
+}
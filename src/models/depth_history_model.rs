@@ -1,8 +1,17 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize)]
+use crate::db::store::Rollup;
+
+// Pool served by `/depths` when no `pool` is requested
+pub const DEFAULT_POOL: &str = "BTC.BTC";
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DepthHistoryInterval {
+    // Midgard leaves the pool implicit in the URL, it is filled in when stored
+    #[serde(default)]
+    pub pool: String,
     pub asset_depth: f64,
     pub asset_price: f64,
     #[serde(rename = "assetPriceUSD")]
@@ -34,12 +43,88 @@ impl DepthHistoryInterval {
             "units",
         ]
     }
-    pub fn has_field(field: String) -> bool {
-        Self::get_feilds().contains(&field.as_str())
+
+    // How hourly intervals are combined into longer buckets: depths, prices and units are
+    // snapshots, so a bucket reports its last hour
+    pub fn rollup() -> Vec<Rollup> {
+        let mut rollup = vec![
+            Rollup::first("pool"),
+            Rollup::first("startTime"),
+            Rollup::last("endTime"),
+        ];
+        rollup.extend(
+            Self::get_feilds()
+                .into_iter()
+                .filter(|field| !matches!(*field, "startTime" | "endTime"))
+                .map(Rollup::last),
+        );
+        rollup
+    }
+
+    // Price of the asset in RUNE, from the pool depths
+    pub fn rune_per_asset(&self) -> f64 {
+        if self.asset_depth > 0.0 {
+            self.rune_depth / self.asset_depth
+        } else {
+            self.asset_price
+        }
+    }
+
+    // Liquidity unit value index, worked out from the depths when Midgard left it at zero
+    pub fn unit_value(&self) -> f64 {
+        if self.luvi > 0.0 || self.units <= 0.0 {
+            self.luvi
+        } else {
+            (self.asset_depth * self.rune_depth).sqrt() / self.units
+        }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+// Growth of the liquidity unit value index from `start` to `end`, as Midgard reports it
+pub fn luvi_increase(start: &DepthHistoryInterval, end: &DepthHistoryInterval) -> f64 {
+    let start_luvi = start.unit_value();
+    if start_luvi > 0.0 {
+        end.unit_value() / start_luvi
+    } else {
+        1.0
+    }
+}
+
+// Value of a pool position relative to holding the deposited assets, caused by the price
+// moving from `start` to `end` alone: 2√r / (1 + r) with r the ratio of the prices
+pub fn price_shift_loss(start: &DepthHistoryInterval, end: &DepthHistoryInterval) -> f64 {
+    let start_price = start.rune_per_asset();
+    if start_price <= 0.0 {
+        return 1.0;
+    }
+    let ratio = end.rune_per_asset() / start_price;
+    2.0 * ratio.sqrt() / (1.0 + ratio)
+}
+
+// An interval together with the LP metrics measured from the first interval of the range
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DepthHistoryIntervalMetrics {
+    #[serde(flatten)]
+    pub interval: DepthHistoryInterval,
+    // Loss against holding caused by the price shift, zero or negative
+    pub impermanent_loss: f64,
+    // Return of the position against holding, fees and rewards included
+    pub lp_return: f64,
+}
+
+impl DepthHistoryIntervalMetrics {
+    pub fn new(start: &DepthHistoryInterval, interval: DepthHistoryInterval) -> Self {
+        let price_shift_loss = price_shift_loss(start, &interval);
+        Self {
+            impermanent_loss: price_shift_loss - 1.0,
+            lp_return: luvi_increase(start, &interval) * price_shift_loss - 1.0,
+            interval,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DepthHistoryMeta {
     pub end_asset_depth: f64,
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::db::store::Rollup;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct EarningHistoryInterval {
     pub start_time: f64,
@@ -31,12 +34,23 @@ impl EarningHistoryInterval {
         ]
     }
 
-    pub fn has_field(field: String) -> bool {
-        Self::field_names().contains(&field.as_str())
+    // How hourly intervals are combined into longer buckets: fees, rewards and earnings
+    // add up, including per pool, while the node count is averaged
+    pub fn rollup() -> Vec<Rollup> {
+        Self::field_names()
+            .into_iter()
+            .map(|field| match field {
+                "startTime" => Rollup::first(field),
+                "endTime" | "runePriceUSD" => Rollup::last(field),
+                "avgNodeCount" => Rollup::avg(field),
+                "pools" => Rollup::sum_by_pool(field, EarningHistoryPool::SUMMED_FIELDS),
+                _ => Rollup::sum(field),
+            })
+            .collect()
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct EarningHistoryPool {
     pub pool: String,
@@ -48,6 +62,18 @@ pub struct EarningHistoryPool {
     pub earnings: f64,
 }
 
+impl EarningHistoryPool {
+    // Fields added up when intervals are combined into longer buckets
+    pub const SUMMED_FIELDS: &'static [&'static str] = &[
+        "assetLiquidityFees",
+        "runeLiquidityFees",
+        "totalLiquidityFeesRune",
+        "saverEarning",
+        "rewards",
+        "earnings",
+    ];
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EarningHistoryMeta {
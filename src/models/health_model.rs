@@ -0,0 +1,29 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StoreStatus {
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+// How far the newest interval of a collection lags behind now
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DatasetFreshness {
+    pub dataset: String,
+    pub latest_start_time: Option<i64>,
+    pub age_seconds: Option<i64>,
+    pub fresh: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub max_age_seconds: i64,
+    pub mongo: StoreStatus,
+    pub datasets: Vec<DatasetFreshness>,
+}
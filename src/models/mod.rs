@@ -1,4 +1,10 @@
+pub mod candle_model;
+pub mod checkpoint_model;
+pub mod dataset;
 pub mod depth_history_model;
 pub mod earning_history_model;
+pub mod health_model;
+pub mod pool_model;
 pub mod rptmuh_model;
 pub mod swap_history_model;
+pub mod sync_job_model;
@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+// The subset of Midgard's `/v2/pools` entries needed for ingestion
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolSummary {
+    pub asset: String,
+    pub status: String,
+}
+
+impl PoolSummary {
+    pub fn is_available(&self) -> bool {
+        self.status == "available"
+    }
+}
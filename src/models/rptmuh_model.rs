@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize)]
+use crate::db::store::Rollup;
+
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RpmuHistoryInterval {
     pub count: f64,
@@ -13,8 +16,16 @@ impl RpmuHistoryInterval {
         vec!["count", "endTime", "startTime", "units"]
     }
 
-    pub fn has_field(field: String) -> bool {
-        Self::field_names().contains(&field.as_str())
+    // How hourly intervals are combined into longer buckets: member counts and units are
+    // snapshots, so a bucket reports its last hour
+    pub fn rollup() -> Vec<Rollup> {
+        Self::field_names()
+            .into_iter()
+            .map(|field| match field {
+                "startTime" => Rollup::first(field),
+                _ => Rollup::last(field),
+            })
+            .collect()
     }
 }
 
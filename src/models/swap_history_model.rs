@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-#[derive(Serialize, Deserialize)]
+use crate::db::store::Rollup;
+
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SwapHistoryInterval {
     pub average_slip: f64,
@@ -95,8 +98,24 @@ impl SwapHistoryInterval {
         ]
     }
 
-    pub fn has_field(field: String) -> bool {
-        Self::field_names().contains(&field.as_str())
+    // How hourly intervals are combined into longer buckets: counts, volumes and fees add
+    // up, slips are averaged by the volume they were measured on
+    pub fn rollup() -> Vec<Rollup> {
+        Self::field_names()
+            .into_iter()
+            .map(|field| match field {
+                "startTime" => Rollup::first(field),
+                "endTime" | "runePriceUSD" => Rollup::last(field),
+                "averageSlip" => Rollup::weighted_avg(field, "totalVolume"),
+                "fromTradeAverageSlip" => Rollup::weighted_avg(field, "fromTradeVolume"),
+                "synthMintAverageSlip" => Rollup::weighted_avg(field, "synthMintVolume"),
+                "synthRedeemAverageSlip" => Rollup::weighted_avg(field, "synthRedeemVolume"),
+                "toAssetAverageSlip" => Rollup::weighted_avg(field, "toAssetVolume"),
+                "toRuneAverageSlip" => Rollup::weighted_avg(field, "toRuneVolume"),
+                "toTradeAverageSlip" => Rollup::weighted_avg(field, "toTradeVolume"),
+                _ => Rollup::sum(field),
+            })
+            .collect()
     }
 }
 
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+// State of the scheduled ingestion job of one dataset. Times are Unix seconds.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncJob {
+    // The dataset name, one job per dataset
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub dataset: String,
+    pub cron: String,
+    pub timeout_secs: i64,
+    pub running: bool,
+    pub last_started_at: Option<i64>,
+    pub last_finished_at: Option<i64>,
+    // End of the last run that left no gaps behind
+    pub last_success_at: Option<i64>,
+    pub last_duration_ms: Option<i64>,
+    pub last_inserted: Option<i64>,
+    // Why the last run failed, cleared by the next successful one
+    pub last_error: Option<String>,
+    pub next_run_at: Option<i64>,
+    pub runs: i64,
+    pub failures: i64,
+    // Ticks skipped because the previous run was still going
+    pub skipped: i64,
+}
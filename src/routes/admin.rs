@@ -0,0 +1,65 @@
+use crate::config::ApiConfig;
+use crate::db::store::HistoryStore;
+use crate::helpers::api_error::ApiError;
+use crate::routes::types::SyncJobsResponse;
+use actix_web::{get, http::header, http::StatusCode, web, HttpRequest, HttpResponse};
+
+// Job state carries Midgard URLs and store errors, so it is only served to requests that
+// send `Authorization: Bearer <api.admin_token>`
+fn authorize(req: &HttpRequest, api_config: &ApiConfig) -> Result<(), ApiError> {
+    let Some(token) = &api_config.admin_token else {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "not_found",
+            "The admin endpoints are disabled.",
+        ));
+    };
+    let sent = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // Compared without stopping at the first mismatch, so timing doesn't leak the token
+    let authorized = sent.is_some_and(|sent| {
+        sent.len() == token.len()
+            && sent
+                .bytes()
+                .zip(token.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    });
+    if !authorized {
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "A valid admin token is required.",
+        ));
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/jobs",
+    description = "State of the scheduled ingestion job of every dataset",
+    responses(
+        (status = 200, body = SyncJobsResponse),
+        (status = 401, description = "Missing or wrong admin token", body = ApiError),
+        (status = 404, description = "No admin token is configured", body = ApiError),
+        (status = 500, description = "History store failure", body = ApiError),
+    )
+)]
+#[get("/admin/jobs")]
+pub async fn handle_jobs(
+    req: HttpRequest,
+    store: web::Data<dyn HistoryStore>,
+    api_config: web::Data<ApiConfig>,
+) -> Result<HttpResponse, ApiError> {
+    authorize(&req, &api_config)?;
+    let jobs = store.list_jobs().await?;
+    Ok(HttpResponse::Ok().json(SyncJobsResponse { jobs }))
+}
+
+pub fn init(config: &mut web::ServiceConfig) {
+    config.service(handle_jobs);
+}
@@ -0,0 +1,73 @@
+use crate::config::ApiConfig;
+use crate::helpers::api_error::ApiError;
+use crate::helpers::export::ExportFormat;
+use crate::helpers::query_parser::QueryParser;
+use crate::helpers::sort::parse_sort;
+use crate::helpers::time_intervals::Bucketing;
+use crate::routes::types::{CandleParams, CandlesResponse, CommonQueryParams};
+use crate::services::candles_service::fetch_candles;
+use crate::{
+    db::store::HistoryStore,
+    models::{candle_model::Candle, depth_history_model::DEFAULT_POOL},
+};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+
+#[utoipa::path(
+    get,
+    path = "/candles",
+    description = "Open, high, low and close of a price per bucket, with the swap volume",
+    params(CommonQueryParams, CandleParams),
+    responses(
+        (status = 200, body = CandlesResponse),
+        (status = 400, description = "Invalid query parameter", body = ApiError),
+        (status = 500, description = "History store failure", body = ApiError),
+    )
+)]
+#[get("/candles")]
+pub async fn handle_candles(
+    req: HttpRequest,
+    store: web::Data<dyn HistoryStore>,
+    config: web::Data<ApiConfig>,
+    query: web::Query<CandleParams>,
+) -> Result<HttpResponse, ApiError> {
+    // Candles join two datasets, so they aren't streamed like the history exports
+    if ExportFormat::from_request(query.common.format.as_deref(), &req)? != ExportFormat::Json {
+        return Err(ApiError::invalid_parameter(
+            "format",
+            "Candles are only available as JSON.",
+        ));
+    }
+    let pagination_params =
+        QueryParser::new(&query.common, config.max_count.candles, config.default_from)?;
+    pagination_params.check_fields(&Candle::field_names())?;
+    // Candles are drawn from a single price, there are no other fields to filter on
+    pagination_params.check_filters(&[])?;
+
+    let pool_name = query.pool.as_deref().unwrap_or(DEFAULT_POOL);
+    let field = query.field.as_deref().unwrap_or("assetPriceUSD");
+    // Volume is joined once the candles are sorted, so it can't be a sort key
+    let mut sort_fields = Candle::field_names();
+    sort_fields.retain(|field| *field != "volume");
+    let sort = parse_sort(
+        query.common.sort.as_deref(),
+        None,
+        query.order.as_deref(),
+        &sort_fields,
+    )?;
+    let bucketing = Bucketing::parse(query.interval.as_deref(), query.tz.as_deref())?;
+
+    let (meta, intervals) = fetch_candles(
+        store.get_ref(),
+        pagination_params,
+        pool_name,
+        field,
+        bucketing,
+        sort,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(CandlesResponse { meta, intervals }))
+}
+
+pub fn init(config: &mut web::ServiceConfig) {
+    config.service(handle_candles);
+}
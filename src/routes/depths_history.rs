@@ -1,53 +1,97 @@
+use crate::config::ApiConfig;
+use crate::helpers::api_error::ApiError;
+use crate::helpers::export::{columns, ExportFormat};
 use crate::helpers::query_parser::QueryParser;
-use crate::routes::types::{DepthHistoryParams, DepthHistoryResponse};
-use crate::services::depths_service::fetch_depths_history;
-use crate::{db::connection::MongoDB, models::depth_history_model::DepthHistoryInterval};
-use actix_web::{get, web, HttpResponse, Responder};
+use crate::helpers::sort::parse_sort;
+use crate::helpers::time_intervals::Bucketing;
+use crate::models::dataset::Dataset;
+use crate::routes::types::{CommonQueryParams, DepthHistoryParams, DepthHistoryResponse};
+use crate::services::depths_service::{
+    depths_query, fetch_depths_history, lp_metrics_rows, range_edges, returns_lp_metrics,
+};
+use crate::services::export_service::export_response;
+use crate::{
+    db::store::HistoryStore,
+    models::depth_history_model::{
+        DepthHistoryInterval, DepthHistoryIntervalMetrics, DEFAULT_POOL,
+    },
+};
+use actix_web::{get, web, HttpRequest, HttpResponse};
 
+#[utoipa::path(
+    get,
+    path = "/depths",
+    description = "Depth, price and membership history of one pool",
+    params(CommonQueryParams, DepthHistoryParams),
+    responses(
+        (status = 200, body = DepthHistoryResponse),
+        (status = 400, description = "Invalid query parameter", body = ApiError),
+        (status = 500, description = "History store failure", body = ApiError),
+    )
+)]
 #[get("/depths")]
 pub async fn handle_depths_history(
-    mongo_db: web::Data<MongoDB>,
+    req: HttpRequest,
+    store: web::Data<dyn HistoryStore>,
+    config: web::Data<ApiConfig>,
     query: web::Query<DepthHistoryParams>,
-) -> impl Responder {
-    let query_params = match QueryParser::new(&query.common, 400) {
-        Ok(params) => params,
-        Err(response) => return response,
-    };
-
-    let interval_str = query.interval.as_deref().unwrap_or("hour");
-    let sort_by = query
-        .sort_by
-        .clone()
-        .unwrap_or_else(|| String::from("startTime"));
+) -> Result<HttpResponse, ApiError> {
+    let format = ExportFormat::from_request(query.common.format.as_deref(), &req)?;
+    let query_params = QueryParser::new(
+        &query.common,
+        format.max_count(config.max_count.depths),
+        config.default_from,
+    )?;
 
-    if !DepthHistoryInterval::has_field(sort_by.clone()) {
-        return HttpResponse::BadRequest().body("Invalid sort_by parameter.");
-    }
-
-    let order = match query.order.as_deref() {
-        Some("asc") => 1,
-        _ => -1,
-    };
+    let pool_name = query.pool.as_deref().unwrap_or(DEFAULT_POOL);
+    let bucketing = Bucketing::parse(query.interval.as_deref(), query.tz.as_deref())?;
+    let sort = parse_sort(
+        query.common.sort.as_deref(),
+        query.sort_by.as_deref(),
+        query.order.as_deref(),
+        &DepthHistoryInterval::get_feilds(),
+    )?;
+    // The LP metrics are returned with the depths and can be picked too
+    let mut field_names = DepthHistoryInterval::get_feilds();
+    field_names.extend(["impermanentLoss", "lpReturn"]);
+    query_params.check_fields(&field_names)?;
+    query_params.check_filters(&DepthHistoryInterval::get_feilds())?;
 
     let max_depth: Option<f64> = query.max_depth;
     let min_depth: Option<f64> = query.min_depth;
     let liquidity_gt: Option<f64> = query.liquidity_gt;
 
-    match fetch_depths_history(
-        &mongo_db,
-        query_params,
-        interval_str,
-        sort_by,
-        order,
+    let columns = columns::<DepthHistoryIntervalMetrics>(query_params.columns(field_names, &sort));
+    let bucket_query = depths_query(
+        &query_params,
+        pool_name,
+        bucketing,
+        sort,
         max_depth,
         min_depth,
         liquidity_gt,
-    )
-    .await
-    {
-        Ok((meta, intervals)) => HttpResponse::Ok().json(DepthHistoryResponse { meta, intervals }),
-        Err(err) => HttpResponse::InternalServerError().body(err),
+    )?;
+    if format != ExportFormat::Json {
+        // The LP metrics of every row are measured from the first interval of the range
+        let map_row = if returns_lp_metrics(&query_params, &bucket_query.sort) {
+            range_edges(store.get_ref(), &bucket_query)
+                .await?
+                .map(|(start, _)| lp_metrics_rows(start))
+        } else {
+            None
+        };
+        return Ok(export_response(
+            store.into_inner(),
+            Dataset::Depths,
+            bucket_query,
+            columns,
+            map_row,
+            format,
+        ));
     }
+    let (meta, intervals) =
+        fetch_depths_history(store.get_ref(), query_params, bucket_query).await?;
+    Ok(HttpResponse::Ok().json(DepthHistoryResponse { meta, intervals }))
 }
 
 pub fn init(config: &mut web::ServiceConfig) {
@@ -1,52 +1,75 @@
+use crate::config::ApiConfig;
+use crate::helpers::api_error::ApiError;
+use crate::helpers::export::{columns, ExportFormat};
 use crate::helpers::query_parser::QueryParser;
+use crate::helpers::sort::parse_sort;
+use crate::helpers::time_intervals::Bucketing;
+use crate::models::dataset::Dataset;
 use crate::models::earning_history_model::EarningHistoryInterval;
-use crate::routes::types::{EarningHistoryParams, EarningHistoryResponse};
-use crate::{db::connection::MongoDB, services::earnings_service::fetch_earnings_history};
-use actix_web::{get, web, HttpResponse, Responder};
+use crate::routes::types::{CommonQueryParams, EarningHistoryParams, EarningHistoryResponse};
+use crate::services::export_service::export_response;
+use crate::{
+    db::store::HistoryStore,
+    services::earnings_service::{earnings_query, fetch_earnings_history},
+};
+use actix_web::{get, web, HttpRequest, HttpResponse};
 
+#[utoipa::path(
+    get,
+    path = "/earnings",
+    description = "Network earnings history, with per pool breakdown",
+    params(CommonQueryParams, EarningHistoryParams),
+    responses(
+        (status = 200, body = EarningHistoryResponse),
+        (status = 400, description = "Invalid query parameter", body = ApiError),
+        (status = 500, description = "History store failure", body = ApiError),
+    )
+)]
 #[get("/earnings")]
 pub async fn handle_earnings_history(
-    mongo_db: web::Data<MongoDB>,
+    req: HttpRequest,
+    store: web::Data<dyn HistoryStore>,
+    config: web::Data<ApiConfig>,
     query: web::Query<EarningHistoryParams>,
-) -> impl Responder {
-    let query_params = match QueryParser::new(&query.common, 100) {
-        Ok(params) => params,
-        Err(response) => return response,
-    };
-    let sort_by = query
-        .sort_by
-        .clone()
-        .unwrap_or_else(|| String::from("startTime"));
-
-    if !EarningHistoryInterval::has_field(sort_by.clone()) {
-        return HttpResponse::BadRequest().body("Invalid sort_by parameter.");
-    }
-    let order = match query.order.as_deref() {
-        Some("asc") => 1,
-        _ => -1,
-    };
+) -> Result<HttpResponse, ApiError> {
+    let format = ExportFormat::from_request(query.common.format.as_deref(), &req)?;
+    let query_params = QueryParser::new(
+        &query.common,
+        format.max_count(config.max_count.earnings),
+        config.default_from,
+    )?;
+    let sort = parse_sort(
+        query.common.sort.as_deref(),
+        query.sort_by.as_deref(),
+        query.order.as_deref(),
+        &EarningHistoryInterval::field_names(),
+    )?;
+    query_params.check_fields(&EarningHistoryInterval::field_names())?;
+    // `pools` is a list and can't be compared with a number
+    let mut numeric_fields = EarningHistoryInterval::field_names();
+    numeric_fields.retain(|field| *field != "pools");
+    query_params.check_filters(&numeric_fields)?;
 
-    let pool_name = match query.pool.as_deref() {
-        Some(val) => val,
-        _ => "all",
-    };
+    let pool_name = query.pool.as_deref().unwrap_or("all");
 
-    let interval_str = query.interval.as_deref().unwrap_or("hour");
-    match fetch_earnings_history(
-        &mongo_db,
-        query_params,
-        &interval_str,
-        sort_by,
-        order,
-        pool_name,
-    )
-    .await
-    {
-        Ok((meta, intervals)) => {
-            HttpResponse::Ok().json(EarningHistoryResponse { meta, intervals })
-        }
-        Err(error_message) => HttpResponse::InternalServerError().body(error_message),
+    let bucketing = Bucketing::parse(query.interval.as_deref(), query.tz.as_deref())?;
+    let columns = columns::<EarningHistoryInterval>(
+        query_params.columns(EarningHistoryInterval::field_names(), &sort),
+    );
+    let bucket_query = earnings_query(&query_params, bucketing, sort, pool_name)?;
+    if format != ExportFormat::Json {
+        return Ok(export_response(
+            store.into_inner(),
+            Dataset::Earnings,
+            bucket_query,
+            columns,
+            None,
+            format,
+        ));
     }
+    let (meta, intervals) =
+        fetch_earnings_history(store.get_ref(), query_params, bucket_query).await?;
+    Ok(HttpResponse::Ok().json(EarningHistoryResponse { meta, intervals }))
 }
 
 pub fn init(config: &mut web::ServiceConfig) {
@@ -0,0 +1,49 @@
+use crate::config::HealthConfig;
+use crate::db::store::HistoryStore;
+use crate::models::health_model::ReadinessReport;
+use crate::services::health_service::check_readiness;
+use actix_web::{get, web, HttpResponse};
+use chrono::Utc;
+use serde_json::json;
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    description = "Liveness probe, answers as long as the server is running",
+    responses((status = 200, description = "The server is running"))
+)]
+#[get("/healthz")]
+pub async fn handle_healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    description = "Readiness probe, checks MongoDB and that every collection has recent intervals",
+    responses(
+        (status = 200, body = ReadinessReport),
+        (status = 503, description = "MongoDB is unreachable or a collection is stale", body = ReadinessReport),
+    )
+)]
+#[get("/readyz")]
+pub async fn handle_readyz(
+    store: web::Data<dyn HistoryStore>,
+    config: web::Data<HealthConfig>,
+) -> HttpResponse {
+    let report = check_readiness(
+        store.get_ref(),
+        Utc::now().timestamp(),
+        config.max_age_hours * 3600,
+    )
+    .await;
+    if report.ready {
+        HttpResponse::Ok().json(report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(report)
+    }
+}
+
+pub fn init(config: &mut web::ServiceConfig) {
+    config.service(handle_healthz).service(handle_readyz);
+}
@@ -0,0 +1,13 @@
+use crate::helpers::metrics::render;
+use actix_web::{get, web, HttpResponse};
+use prometheus::TEXT_FORMAT;
+
+// Request, Midgard and ingestion metrics in the Prometheus text format
+#[get("/metrics")]
+pub async fn handle_metrics() -> HttpResponse {
+    HttpResponse::Ok().content_type(TEXT_FORMAT).body(render())
+}
+
+pub fn init(config: &mut web::ServiceConfig) {
+    config.service(handle_metrics);
+}
@@ -1,5 +1,10 @@
+pub mod admin;
+pub mod candles;
 pub mod depths_history;
 pub mod earnings_history;
+pub mod health;
+pub mod metrics;
+pub mod openapi;
 pub mod rpmuh_history;
 pub mod swaps_history;
 pub mod types;
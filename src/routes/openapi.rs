@@ -0,0 +1,107 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use utoipa::openapi::{schema::Type, ObjectBuilder};
+use utoipa::OpenApi;
+use utoipa_scalar::{Scalar, Servable};
+
+use crate::helpers::{api_error::ApiError, time_intervals::Interval};
+use crate::models::{
+    candle_model::{Candle, CANDLE_FIELDS},
+    depth_history_model::{DepthHistoryInterval, DepthHistoryIntervalMetrics, DepthHistoryMeta},
+    earning_history_model::{EarningHistoryInterval, EarningHistoryPool},
+    health_model::{DatasetFreshness, ReadinessReport, StoreStatus},
+    rptmuh_model::RpmuHistoryInterval,
+    swap_history_model::SwapHistoryInterval,
+    sync_job_model::SyncJob,
+};
+use crate::routes::{
+    admin, candles, depths_history, earnings_history, health, rpmuh_history, swaps_history, types,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Midgard history API"),
+    paths(
+        admin::handle_jobs,
+        candles::handle_candles,
+        depths_history::handle_depths_history,
+        earnings_history::handle_earnings_history,
+        health::handle_healthz,
+        health::handle_readyz,
+        rpmuh_history::get_member_data,
+        swaps_history::handle_swaps_history,
+    ),
+    components(schemas(
+        ApiError,
+        Candle,
+        DepthHistoryInterval,
+        DepthHistoryIntervalMetrics,
+        DatasetFreshness,
+        DepthHistoryMeta,
+        EarningHistoryInterval,
+        EarningHistoryPool,
+        ReadinessReport,
+        RpmuHistoryInterval,
+        StoreStatus,
+        SwapHistoryInterval,
+        SyncJob,
+        types::CandlesMeta,
+        types::CandlesResponse,
+        types::DepthHistoryResponse,
+        types::DepthsHistoryMeta,
+        types::EarningHistoryFlattenMeta,
+        types::EarningHistoryResponse,
+        types::RpmuHistoryMeta,
+        types::RpmuHistoryResponse,
+        types::SwapHistoryMeta,
+        types::SwapHistoryResponse,
+        types::SyncJobsResponse,
+    ))
+)]
+pub struct ApiDoc;
+
+// String schema restricted to `values`. The enums below are built from the same lists the
+// handlers validate against, so the spec follows the models.
+fn one_of(values: Vec<&'static str>) -> ObjectBuilder {
+    ObjectBuilder::new()
+        .schema_type(Type::String)
+        .enum_values(Some(values))
+}
+
+pub fn candle_fields() -> ObjectBuilder {
+    one_of(CANDLE_FIELDS.iter().map(|(field, _)| *field).collect())
+}
+
+pub fn depth_sort_fields() -> ObjectBuilder {
+    one_of(DepthHistoryInterval::get_feilds())
+}
+
+pub fn earning_sort_fields() -> ObjectBuilder {
+    one_of(EarningHistoryInterval::field_names())
+}
+
+pub fn rpmu_sort_fields() -> ObjectBuilder {
+    one_of(RpmuHistoryInterval::field_names())
+}
+
+pub fn swap_sort_fields() -> ObjectBuilder {
+    one_of(SwapHistoryInterval::field_names())
+}
+
+pub fn interval_names() -> ObjectBuilder {
+    one_of(Interval::ALL.iter().map(Interval::name).collect())
+}
+
+pub fn sort_orders() -> ObjectBuilder {
+    one_of(vec!["asc", "desc"])
+}
+
+#[get("/openapi.json")]
+async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+pub fn init(config: &mut web::ServiceConfig) {
+    config
+        .service(openapi_json)
+        .service(Scalar::with_url("/docs", ApiDoc::openapi()));
+}
@@ -1,38 +1,68 @@
+use crate::config::ApiConfig;
+use crate::helpers::api_error::ApiError;
+use crate::helpers::export::{columns, ExportFormat};
 use crate::helpers::query_parser::QueryParser;
-use crate::routes::types::{RpmuHistoryQuery, RpmuHistoryResponse};
-use crate::services::rpmuh_service::fetch_rpmuh_data;
-use crate::{db::connection::MongoDB, models::rptmuh_model::RpmuHistoryInterval};
-use actix_web::{get, web, HttpResponse, Responder};
+use crate::helpers::sort::parse_sort;
+use crate::helpers::time_intervals::Bucketing;
+use crate::models::dataset::Dataset;
+use crate::routes::types::{CommonQueryParams, RpmuHistoryQuery, RpmuHistoryResponse};
+use crate::services::export_service::export_response;
+use crate::services::rpmuh_service::{fetch_rpmuh_data, rpmuh_query};
+use crate::{db::store::HistoryStore, models::rptmuh_model::RpmuHistoryInterval};
+use actix_web::{get, web, HttpRequest, HttpResponse};
 
+#[utoipa::path(
+    get,
+    path = "/history/runepool",
+    description = "RUNEPool member and unit history",
+    params(CommonQueryParams, RpmuHistoryQuery),
+    responses(
+        (status = 200, body = RpmuHistoryResponse),
+        (status = 400, description = "Invalid query parameter", body = ApiError),
+        (status = 500, description = "History store failure", body = ApiError),
+    )
+)]
 #[get("/history/runepool")]
 pub async fn get_member_data(
-    mongo_db: web::Data<MongoDB>,
+    req: HttpRequest,
+    store: web::Data<dyn HistoryStore>,
+    config: web::Data<ApiConfig>,
     query: web::Query<RpmuHistoryQuery>,
-) -> impl Responder {
-    let pagination_params = match QueryParser::new(&query.common, 400) {
-        Ok(params) => params,
-        Err(response) => return response,
-    };
-    let sort_by = query
-        .sort_by
-        .clone()
-        .unwrap_or_else(|| String::from("startTime"));
+) -> Result<HttpResponse, ApiError> {
+    let format = ExportFormat::from_request(query.common.format.as_deref(), &req)?;
+    let pagination_params = QueryParser::new(
+        &query.common,
+        format.max_count(config.max_count.runepool),
+        config.default_from,
+    )?;
+    let sort = parse_sort(
+        query.common.sort.as_deref(),
+        query.sort_by.as_deref(),
+        query.order.as_deref(),
+        &RpmuHistoryInterval::field_names(),
+    )?;
+    pagination_params.check_fields(&RpmuHistoryInterval::field_names())?;
+    pagination_params.check_filters(&RpmuHistoryInterval::field_names())?;
 
-    if !RpmuHistoryInterval::has_field(sort_by.clone()) {
-        return HttpResponse::BadRequest().body("Invalid sort_by parameter.");
-    }
-
-    let order = match query.order.as_deref() {
-        Some("asc") => 1,
-        _ => -1,
-    };
-
-    let interval_str = query.interval.clone().unwrap_or_else(|| "hour".to_string());
+    let bucketing = Bucketing::parse(query.interval.as_deref(), query.tz.as_deref())?;
 
-    match fetch_rpmuh_data(&mongo_db, pagination_params, &interval_str, sort_by, order).await {
-        Ok((meta, intervals)) => HttpResponse::Ok().json(RpmuHistoryResponse { meta, intervals }),
-        Err(error_message) => HttpResponse::InternalServerError().body(error_message),
+    let columns = columns::<RpmuHistoryInterval>(
+        pagination_params.columns(RpmuHistoryInterval::field_names(), &sort),
+    );
+    let bucket_query = rpmuh_query(&pagination_params, bucketing, sort)?;
+    if format != ExportFormat::Json {
+        return Ok(export_response(
+            store.into_inner(),
+            Dataset::Members,
+            bucket_query,
+            columns,
+            None,
+            format,
+        ));
     }
+    let (meta, intervals) =
+        fetch_rpmuh_data(store.get_ref(), pagination_params, bucket_query).await?;
+    Ok(HttpResponse::Ok().json(RpmuHistoryResponse { meta, intervals }))
 }
 
 pub fn init(config: &mut web::ServiceConfig) {
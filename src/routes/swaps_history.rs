@@ -1,37 +1,68 @@
+use crate::config::ApiConfig;
+use crate::helpers::api_error::ApiError;
+use crate::helpers::export::{columns, ExportFormat};
 use crate::helpers::query_parser::QueryParser;
-use crate::routes::types::{SwapHistoryParams, SwapHistoryResponse};
-use crate::services::swaps_service::fetch_swaps_history;
-use crate::{db::connection::MongoDB, models::swap_history_model::SwapHistoryInterval};
-use actix_web::{get, web, HttpResponse, Responder};
+use crate::helpers::sort::parse_sort;
+use crate::helpers::time_intervals::Bucketing;
+use crate::models::dataset::Dataset;
+use crate::routes::types::{CommonQueryParams, SwapHistoryParams, SwapHistoryResponse};
+use crate::services::export_service::export_response;
+use crate::services::swaps_service::{fetch_swaps_history, swaps_query};
+use crate::{db::store::HistoryStore, models::swap_history_model::SwapHistoryInterval};
+use actix_web::{get, web, HttpRequest, HttpResponse};
 
+#[utoipa::path(
+    get,
+    path = "/swaps",
+    description = "Swap count, volume, fee and slip history",
+    params(CommonQueryParams, SwapHistoryParams),
+    responses(
+        (status = 200, body = SwapHistoryResponse),
+        (status = 400, description = "Invalid query parameter", body = ApiError),
+        (status = 500, description = "History store failure", body = ApiError),
+    )
+)]
 #[get("/swaps")]
 pub async fn handle_swaps_history(
-    mongo_db: web::Data<MongoDB>,
+    req: HttpRequest,
+    store: web::Data<dyn HistoryStore>,
+    config: web::Data<ApiConfig>,
     query: web::Query<SwapHistoryParams>,
-) -> impl Responder {
-    let pagination_params = match QueryParser::new(&query.common, 400) {
-        Ok(params) => params,
-        Err(response) => return response,
-    };
+) -> Result<HttpResponse, ApiError> {
+    let format = ExportFormat::from_request(query.common.format.as_deref(), &req)?;
+    let pagination_params = QueryParser::new(
+        &query.common,
+        format.max_count(config.max_count.swaps),
+        config.default_from,
+    )?;
 
-    let sort_by = query
-        .sort_by
-        .clone()
-        .unwrap_or_else(|| String::from("startTime"));
+    let sort = parse_sort(
+        query.common.sort.as_deref(),
+        query.sort_by.as_deref(),
+        query.order.as_deref(),
+        &SwapHistoryInterval::field_names(),
+    )?;
+    pagination_params.check_fields(&SwapHistoryInterval::field_names())?;
+    pagination_params.check_filters(&SwapHistoryInterval::field_names())?;
 
-    if !SwapHistoryInterval::has_field(sort_by.clone()) {
-        return HttpResponse::BadRequest().body("Invalid sort_by parameter.");
-    }
-
-    let order = match query.order.as_deref() {
-        Some("asc") => 1,
-        _ => -1,
-    };
-    let interval_str = query.interval.as_deref().unwrap_or("hour");
-    match fetch_swaps_history(&mongo_db, pagination_params, interval_str, sort_by, order).await {
-        Ok((meta, intervals)) => HttpResponse::Ok().json(SwapHistoryResponse { meta, intervals }),
-        Err(error_message) => HttpResponse::InternalServerError().body(error_message),
+    let bucketing = Bucketing::parse(query.interval.as_deref(), query.tz.as_deref())?;
+    let columns = columns::<SwapHistoryInterval>(
+        pagination_params.columns(SwapHistoryInterval::field_names(), &sort),
+    );
+    let bucket_query = swaps_query(&pagination_params, bucketing, sort)?;
+    if format != ExportFormat::Json {
+        return Ok(export_response(
+            store.into_inner(),
+            Dataset::Swaps,
+            bucket_query,
+            columns,
+            None,
+            format,
+        ));
     }
+    let (meta, intervals) =
+        fetch_swaps_history(store.get_ref(), pagination_params, bucket_query).await?;
+    Ok(HttpResponse::Ok().json(SwapHistoryResponse { meta, intervals }))
 }
 
 pub fn init(config: &mut web::ServiceConfig) {
@@ -1,53 +1,102 @@
-use serde::{Deserialize, Serialize};
+use mongodb::bson::Document;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::db::store::{from_documents, to_documents, StoreError};
+use crate::helpers::{query_parser::QueryParser, sort::SortKey};
 
 use crate::models::{
-    depth_history_model::{DepthHistoryInterval, DepthHistoryMeta},
+    candle_model::Candle,
+    depth_history_model::{DepthHistoryIntervalMetrics, DepthHistoryMeta},
     earning_history_model::EarningHistoryInterval,
     rptmuh_model::RpmuHistoryInterval,
     swap_history_model::SwapHistoryInterval,
+    sync_job_model::SyncJob,
+};
+use crate::routes::openapi::{
+    candle_fields, depth_sort_fields, earning_sort_fields, interval_names, rpmu_sort_fields,
+    sort_orders, swap_sort_fields,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SwapHistoryParams {
     #[serde(flatten)]
+    #[param(ignore)]
     pub common: CommonQueryParams,
+    /// Bucket size, `hour` by default
+    #[param(schema_with = interval_names)]
     pub interval: Option<String>,
+    /// IANA timezone whose calendar the buckets follow, UTC by default
+    pub tz: Option<String>,
+    #[param(schema_with = swap_sort_fields)]
     pub sort_by: Option<String>,
+    #[param(schema_with = sort_orders)]
     pub order: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SwapHistoryMeta {
     pub current_page: i64,
     pub count: i64,
     pub has_next_page: bool,
+    pub next_cursor: Option<String>,
+    // Buckets across all pages
+    pub total: u64,
+    pub total_pages: u64,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct SwapHistoryResponse {
     pub meta: SwapHistoryMeta,
-    pub intervals: Vec<SwapHistoryInterval>,
+    #[schema(value_type = Vec<SwapHistoryInterval>)]
+    pub intervals: Intervals<SwapHistoryInterval>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct CommonQueryParams {
+    /// Page number, starting at 1
     pub page: Option<String>,
+    /// Buckets per page
     pub count: Option<String>,
+    /// Start of the range, `YYYY-MM-DDTHH:MM:SS` in UTC
     pub from: Option<String>,
+    /// End of the range, `YYYY-MM-DDTHH:MM:SS` in UTC
     pub to: Option<String>,
+    /// `nextCursor` of the previous page; takes precedence over `page`
+    pub cursor: Option<String>,
+    /// `json`, `csv`, `ndjson` or `parquet`; defaults to the `Accept` header
+    pub format: Option<String>,
+    /// Comma separated fields to return; `startTime`, the sort fields and the filtered fields
+    /// are always included
+    pub fields: Option<String>,
+    /// Comma separated sort keys, `-` for descending, e.g. `-totalVolumeUSD,startTime`;
+    /// replaces `sort_by` and `order`
+    pub sort: Option<String>,
+    /// Comma separated conditions on the buckets, e.g. `totalVolumeUSD>1e6,averageSlip<=20`
+    pub filter: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct RpmuHistoryQuery {
     #[serde(flatten)]
+    #[param(ignore)]
     pub common: CommonQueryParams,
+    /// Bucket size, `hour` by default
+    #[param(schema_with = interval_names)]
     pub interval: Option<String>,
+    /// IANA timezone whose calendar the buckets follow, UTC by default
+    pub tz: Option<String>,
+    #[param(schema_with = rpmu_sort_fields)]
     pub sort_by: Option<String>,
+    #[param(schema_with = sort_orders)]
     pub order: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct RpmuHistoryMeta {
     pub end_count: String,
@@ -59,50 +108,78 @@ pub struct RpmuHistoryMeta {
     pub current_page: i64,
     pub count: i64,
     pub has_next_page: bool,
+    pub next_cursor: Option<String>,
+    // Buckets across all pages
+    pub total: u64,
+    pub total_pages: u64,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct RpmuHistoryResponse {
     pub meta: RpmuHistoryMeta,
-    pub intervals: Vec<RpmuHistoryInterval>,
+    #[schema(value_type = Vec<RpmuHistoryInterval>)]
+    pub intervals: Intervals<RpmuHistoryInterval>,
 }
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct EarningHistoryParams {
     #[serde(flatten)]
+    #[param(ignore)]
     pub common: CommonQueryParams,
+    /// Bucket size, `hour` by default
+    #[param(schema_with = interval_names)]
     pub interval: Option<String>,
+    /// IANA timezone whose calendar the buckets follow, UTC by default
+    pub tz: Option<String>,
+    #[param(schema_with = earning_sort_fields)]
     pub sort_by: Option<String>,
+    #[param(schema_with = sort_orders)]
     pub order: Option<String>,
+    /// Only keep this pool in `pools`, `all` by default
     pub pool: Option<String>,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-
 pub struct EarningHistoryFlattenMeta {
     pub count: i64,
     pub page: i64,
     pub has_next_page: bool,
+    pub next_cursor: Option<String>,
+    // Buckets across all pages
+    pub total: u64,
+    pub total_pages: u64,
 }
-#[derive(Deserialize, Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct EarningHistoryResponse {
     pub meta: EarningHistoryFlattenMeta,
-    pub intervals: Vec<EarningHistoryInterval>,
+    #[schema(value_type = Vec<EarningHistoryInterval>)]
+    pub intervals: Intervals<EarningHistoryInterval>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct DepthHistoryParams {
     #[serde(flatten)]
+    #[param(ignore)]
     pub common: CommonQueryParams,
+    /// Pool asset, `BTC.BTC` by default
+    pub pool: Option<String>,
+    /// Bucket size, `hour` by default
+    #[param(schema_with = interval_names)]
     pub interval: Option<String>,
+    /// IANA timezone whose calendar the buckets follow, UTC by default
+    pub tz: Option<String>,
+    #[param(schema_with = depth_sort_fields)]
     pub sort_by: Option<String>,
+    #[param(schema_with = sort_orders)]
     pub order: Option<String>,
     pub min_depth: Option<f64>,
     pub max_depth: Option<f64>,
     pub liquidity_gt: Option<f64>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DepthsHistoryMeta {
     #[serde(flatten)]
@@ -110,9 +187,105 @@ pub struct DepthsHistoryMeta {
     pub current_page: i64,
     pub count: i64,
     pub has_next_page: bool,
+    pub next_cursor: Option<String>,
+    // Buckets across all pages
+    pub total: u64,
+    pub total_pages: u64,
 }
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct DepthHistoryResponse {
     pub meta: DepthsHistoryMeta,
-    pub intervals: Vec<DepthHistoryInterval>,
+    #[schema(value_type = Vec<DepthHistoryIntervalMetrics>)]
+    pub intervals: Intervals<DepthHistoryIntervalMetrics>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CandleParams {
+    #[serde(flatten)]
+    #[param(ignore)]
+    pub common: CommonQueryParams,
+    /// Pool asset, `BTC.BTC` by default; not used for `runePriceUSD`
+    pub pool: Option<String>,
+    /// Price the candles are drawn from, `assetPriceUSD` by default
+    #[param(schema_with = candle_fields)]
+    pub field: Option<String>,
+    /// Bucket size, `hour` by default
+    #[param(schema_with = interval_names)]
+    pub interval: Option<String>,
+    /// IANA timezone whose calendar the buckets follow, UTC by default
+    pub tz: Option<String>,
+    #[param(schema_with = sort_orders)]
+    pub order: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CandlesMeta {
+    pub pool: Option<String>,
+    pub field: String,
+    pub current_page: i64,
+    pub count: i64,
+    pub has_next_page: bool,
+    pub next_cursor: Option<String>,
+    // Buckets across all pages
+    pub total: u64,
+    pub total_pages: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CandlesResponse {
+    pub meta: CandlesMeta,
+    #[schema(value_type = Vec<Candle>)]
+    pub intervals: Intervals<Candle>,
+}
+
+// Buckets of a response, holding only the returned fields when `fields=` was given
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Intervals<T> {
+    Full(Vec<T>),
+    Sparse(Vec<Document>),
+}
+
+impl<T: DeserializeOwned> Intervals<T> {
+    // Buckets of a projected rollup already hold just the returned fields
+    pub fn from_documents(
+        documents: Vec<Document>,
+        params: &QueryParser,
+    ) -> Result<Self, StoreError> {
+        match params.fields {
+            Some(_) => Ok(Intervals::Sparse(documents)),
+            None => Ok(Intervals::Full(from_documents(documents)?)),
+        }
+    }
+}
+
+impl<T: Serialize> Intervals<T> {
+    // Buckets computed in full, trimmed to the returned fields
+    pub fn select(
+        items: Vec<T>,
+        params: &QueryParser,
+        sort: &[SortKey],
+    ) -> Result<Self, StoreError> {
+        if params.fields.is_none() {
+            return Ok(Intervals::Full(items));
+        }
+        Ok(Intervals::Sparse(
+            to_documents(&items)?
+                .into_iter()
+                .map(|document| {
+                    document
+                        .into_iter()
+                        .filter(|(field, _)| params.keeps(field, sort))
+                        .collect()
+                })
+                .collect(),
+        ))
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SyncJobsResponse {
+    pub jobs: Vec<SyncJob>,
 }
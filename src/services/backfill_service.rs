@@ -0,0 +1,295 @@
+use chrono::Utc;
+use mongodb::bson::{doc, Document};
+use serde::Serialize;
+use std::error::Error;
+use std::future::Future;
+use tracing::{error, info, instrument, warn};
+
+use crate::db::store::{bson_to_f64, to_documents, HistoryStore, StoreError};
+use crate::helpers::gaps::{align_to_hour, hourly_pages, missing_ranges, TimeRange};
+use crate::helpers::metrics::count_ingested;
+use crate::helpers::shutdown::Shutdown;
+use crate::midgard::client::{HistoryQuery, MidgardClient};
+use crate::midgard::error::MidgardError;
+use crate::models::{
+    checkpoint_model::SyncCheckpoint, dataset::Dataset, depth_history_model::DEFAULT_POOL,
+};
+use crate::services::{
+    depths_service::update_depths_data, earnings_service::update_earnings_history,
+    rpmuh_service::update_rpmuh_data, swaps_service::update_swaps_history,
+};
+
+#[derive(Debug)]
+pub struct GapReport {
+    pub dataset: Dataset,
+    pub pool: Option<String>,
+    pub gaps: Vec<TimeRange>,
+    pub filled: Vec<TimeRange>,
+    pub failed: Vec<TimeRange>,
+    pub inserted: u64,
+}
+
+impl GapReport {
+    pub fn missing_hours(&self) -> i64 {
+        self.gaps.iter().map(TimeRange::hours).sum()
+    }
+
+    pub fn filled_hours(&self) -> i64 {
+        self.filled.iter().map(TimeRange::hours).sum()
+    }
+}
+
+// Scans the `startTime` values stored for `dataset` and returns the hourly buckets
+// missing between `from` and `to`. Depths are tracked per pool.
+pub async fn find_gaps(
+    store: &dyn HistoryStore,
+    dataset: Dataset,
+    pool: Option<&str>,
+    from: i64,
+    to: i64,
+) -> Result<Vec<TimeRange>, StoreError> {
+    let existing = store
+        .start_times(dataset, scope(dataset, pool), from, to)
+        .await?;
+    Ok(missing_ranges(&existing, from, to))
+}
+
+// `startTime` of the oldest stored interval of `dataset`, for `pool` with depths
+pub async fn history_start(
+    store: &dyn HistoryStore,
+    dataset: Dataset,
+    pool: Option<&str>,
+) -> Result<Option<i64>, StoreError> {
+    store
+        .earliest_start_time(dataset, scope(dataset, pool))
+        .await
+}
+
+fn scope(dataset: Dataset, pool: Option<&str>) -> Document {
+    match dataset {
+        Dataset::Depths => doc! { "pool": pool.unwrap_or(DEFAULT_POOL) },
+        _ => doc! {},
+    }
+}
+
+// Fetches `[from, to)` one Midgard page at a time with `fetch` and upserts the intervals
+// under `scope`, returning how many were new
+pub async fn ingest_pages<T, F, Fut>(
+    store: &dyn HistoryStore,
+    dataset: Dataset,
+    scope: Document,
+    from: f64,
+    to: f64,
+    fetch: F,
+) -> Result<u64, Box<dyn Error>>
+where
+    T: Serialize,
+    F: Fn(HistoryQuery) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, MidgardError>>,
+{
+    if from >= to {
+        return Err("Invalid time range: 'from' should be less than 'to'".into());
+    }
+
+    let mut inserted = 0;
+    for page in hourly_pages(from as i64, to as i64) {
+        let query = HistoryQuery {
+            count: None,
+            from: Some(page.from),
+            to: Some(page.to),
+        };
+        let intervals = fetch(query).await.map_err(|e| {
+            error!(error = %e, "Failed to fetch data");
+            e
+        })?;
+
+        // Midgard also returns the bucket that is still in progress at `to`
+        let intervals: Vec<Document> = to_documents(&intervals)?
+            .into_iter()
+            .filter(|interval| {
+                interval
+                    .get("startTime")
+                    .and_then(bson_to_f64)
+                    .is_some_and(|start| start >= page.from as f64 && start < page.to as f64)
+            })
+            .collect();
+        if intervals.is_empty() {
+            continue;
+        }
+
+        let result = store.upsert(dataset, scope.clone(), intervals).await?;
+
+        info!(
+            from = page.from,
+            to = page.to,
+            rows = result.matched + result.upserted,
+            new = result.upserted,
+            "Upserted intervals"
+        );
+        inserted += result.upserted;
+    }
+    Ok(inserted)
+}
+
+// Fetches `[from, to)` of `dataset` from Midgard through the matching update service
+#[instrument(skip(store, midgard))]
+pub async fn fetch_range(
+    store: &dyn HistoryStore,
+    midgard: &MidgardClient,
+    dataset: Dataset,
+    pool: Option<&str>,
+    from: i64,
+    to: i64,
+) -> Result<u64, Box<dyn Error>> {
+    let (from, to) = (from as f64, to as f64);
+    let inserted = match dataset {
+        Dataset::Depths => {
+            let pool_name = pool.unwrap_or(DEFAULT_POOL).to_string();
+            update_depths_data(store, midgard, pool_name, from, to).await
+        }
+        Dataset::Earnings => update_earnings_history(store, midgard, from, to).await,
+        Dataset::Members => update_rpmuh_data(store, midgard, from, to).await,
+        Dataset::Swaps => update_swaps_history(store, midgard, from, to).await,
+    }?;
+    count_ingested(dataset, inserted);
+    Ok(inserted)
+}
+
+// Finds the missing hourly buckets of `dataset` in `[from, to)` and fetches just those ranges.
+// Once shutdown is requested the gap being fetched is finished and the rest are left.
+pub async fn fill_gaps(
+    store: &dyn HistoryStore,
+    midgard: &MidgardClient,
+    dataset: Dataset,
+    pool: Option<&str>,
+    from: i64,
+    to: i64,
+    shutdown: &Shutdown,
+) -> Result<GapReport, StoreError> {
+    let gaps = find_gaps(store, dataset, pool, from, to).await?;
+    let mut report = GapReport {
+        dataset,
+        pool: pool.map(String::from),
+        gaps: gaps.clone(),
+        filled: Vec::new(),
+        failed: Vec::new(),
+        inserted: 0,
+    };
+
+    for gap in gaps {
+        if shutdown.is_requested() {
+            break;
+        }
+        let result = fetch_range(store, midgard, dataset, pool, gap.from, gap.to).await;
+        match result {
+            Ok(inserted) => {
+                report.inserted += inserted;
+                report.filled.push(gap);
+            }
+            Err(e) => {
+                error!(%dataset, %gap, error = %e, "Error filling gap");
+                report.failed.push(gap);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+// Pools to ingest depths for: everything Midgard lists as available, or the pools already
+// stored when discovery fails
+pub async fn discover_pools(store: &dyn HistoryStore, midgard: &MidgardClient) -> Vec<String> {
+    match midgard.available_pools().await {
+        Ok(pools) if !pools.is_empty() => pools.into_iter().map(|pool| pool.asset).collect(),
+        Ok(_) => known_pools(store).await,
+        Err(e) => {
+            warn!(error = %e, "Error discovering pools, using stored pools");
+            known_pools(store).await
+        }
+    }
+}
+
+async fn known_pools(store: &dyn HistoryStore) -> Vec<String> {
+    let mut pools = store.depth_pools().await.unwrap_or_default();
+    if pools.is_empty() {
+        pools.push(DEFAULT_POOL.to_string());
+    }
+    pools
+}
+
+// Fetches `[from, to)` page by page, recording a checkpoint after each page so a run that
+// is interrupted, or stopped through `shutdown`, continues from the last stored page when
+// started again. Without `to`, an interrupted run keeps the end it started with and a
+// completed one catches up to the current hour.
+pub async fn run_backfill(
+    store: &dyn HistoryStore,
+    midgard: &MidgardClient,
+    dataset: Dataset,
+    pool: Option<&str>,
+    from: i64,
+    to: Option<i64>,
+    shutdown: &Shutdown,
+) -> Result<u64, Box<dyn Error>> {
+    let pool = match dataset {
+        Dataset::Depths => Some(pool.unwrap_or(DEFAULT_POOL)),
+        _ => None,
+    };
+
+    let id = SyncCheckpoint::key(dataset.name(), pool, from);
+    let stored = store.load_checkpoint(&id).await?;
+    let to = match (&stored, to) {
+        (_, Some(to)) => to,
+        (Some(checkpoint), None) if !checkpoint.completed => checkpoint.to,
+        _ => align_to_hour(Utc::now().timestamp()),
+    };
+    if from >= to {
+        return Err("Invalid time range: 'from' should be less than 'to'".into());
+    }
+
+    let mut checkpoint = match stored {
+        Some(checkpoint) if checkpoint.cursor >= to => {
+            info!(backfill = %id, to, "Backfill already completed");
+            return Ok(0);
+        }
+        Some(mut checkpoint) => {
+            info!(backfill = %id, cursor = checkpoint.cursor, to, "Resuming backfill");
+            checkpoint.to = to;
+            checkpoint.completed = false;
+            checkpoint
+        }
+        None => SyncCheckpoint {
+            id,
+            dataset: dataset.name().to_string(),
+            pool: pool.map(String::from),
+            from,
+            to,
+            cursor: from,
+            completed: false,
+            updated_at: Utc::now().timestamp(),
+        },
+    };
+
+    let mut inserted = 0;
+    for page in hourly_pages(checkpoint.cursor, to) {
+        if shutdown.is_requested() {
+            info!(
+                backfill = %checkpoint.id,
+                cursor = checkpoint.cursor,
+                "Backfill stopped, run it again to resume"
+            );
+            break;
+        }
+        inserted += fetch_range(store, midgard, dataset, pool, page.from, page.to).await?;
+        checkpoint.cursor = page.to;
+        checkpoint.completed = page.to >= to;
+        checkpoint.updated_at = Utc::now().timestamp();
+        store.save_checkpoint(&checkpoint).await?;
+        info!(
+            backfill = %checkpoint.id,
+            cursor = checkpoint.cursor,
+            inserted,
+            "Backfill stored a page"
+        );
+    }
+    Ok(inserted)
+}
@@ -0,0 +1,109 @@
+use crate::db::store::{bson_to_f64, from_documents, BucketQuery, HistoryStore};
+use crate::helpers::api_error::ApiError;
+use crate::helpers::query_parser::QueryParser;
+use crate::helpers::sort::SortKey;
+use crate::helpers::time_intervals::Bucketing;
+use crate::models::candle_model::Candle;
+use crate::models::dataset::Dataset;
+use crate::routes::types::{CandlesMeta, Intervals};
+use mongodb::bson::doc;
+use tracing::instrument;
+
+#[instrument(skip(store, pagination_params, bucketing, sort))]
+pub async fn fetch_candles(
+    store: &dyn HistoryStore,
+    pagination_params: QueryParser,
+    pool_name: &str,
+    field: &str,
+    bucketing: Bucketing,
+    sort: Vec<SortKey>,
+) -> Result<(CandlesMeta, Intervals<Candle>), ApiError> {
+    let (field, dataset) = Candle::source(field).ok_or_else(|| {
+        ApiError::invalid_parameter("field", "Invalid field, expected a price field.")
+    })?;
+    let mut filter = pagination_params.date_filter();
+    let pool = match dataset {
+        Dataset::Depths => {
+            filter.insert("pool", pool_name);
+            Some(pool_name.to_string())
+        }
+        // RUNE prices are the same for every pool
+        _ => None,
+    };
+
+    let after = pagination_params.after(&sort)?;
+    let query = BucketQuery {
+        filter,
+        conditions: doc! {},
+        pools_filter: None,
+        bucketing,
+        rollup: Candle::rollup(field),
+        sort,
+        skip: pagination_params.skip(),
+        limit: pagination_params.count,
+        after,
+    };
+    let page = store.bucketed(dataset, &query).await?;
+    let next_cursor = page.next_cursor(&query);
+    let has_next_page = page.has_next_page();
+    let total_pages = page.total_pages(pagination_params.count);
+    let mut candles: Vec<Candle> = from_documents(page.documents)?;
+    add_volume(store, bucketing, &mut candles).await?;
+
+    let meta = CandlesMeta {
+        pool,
+        field: field.to_string(),
+        current_page: pagination_params.page,
+        count: candles.len() as i64,
+        has_next_page,
+        next_cursor,
+        total: page.total,
+        total_pages,
+    };
+    let intervals = Intervals::select(candles, &pagination_params, &query.sort)?;
+    Ok((meta, intervals))
+}
+
+// Fills in the swap volume of the buckets spanned by `candles`. Both datasets are bucketed
+// the same way, so a volume bucket belongs to the candle whose time span it overlaps.
+async fn add_volume(
+    store: &dyn HistoryStore,
+    bucketing: Bucketing,
+    candles: &mut [Candle],
+) -> Result<(), ApiError> {
+    let start = candles
+        .iter()
+        .map(|candle| candle.start_time)
+        .reduce(f64::min);
+    let end = candles
+        .iter()
+        .map(|candle| candle.end_time)
+        .reduce(f64::max);
+    let (Some(start), Some(end)) = (start, end) else {
+        return Ok(());
+    };
+
+    let query = BucketQuery {
+        filter: doc! { "startTime": { "$gte": start, "$lt": end } },
+        conditions: doc! {},
+        pools_filter: None,
+        bucketing,
+        rollup: Candle::volume_rollup(),
+        sort: vec![SortKey::new("startTime", 1)],
+        skip: 0,
+        limit: i64::MAX,
+        after: None,
+    };
+    let page = store.bucketed(Dataset::Swaps, &query).await?;
+    for volume in page.documents {
+        let number = |field| volume.get(field).and_then(bson_to_f64).unwrap_or_default();
+        let (volume_start, volume_end) = (number("startTime"), number("endTime"));
+        let candle = candles
+            .iter_mut()
+            .find(|candle| volume_start < candle.end_time && candle.start_time < volume_end);
+        if let Some(candle) = candle {
+            candle.volume = number("totalVolume");
+        }
+    }
+    Ok(())
+}
@@ -1,177 +1,226 @@
-use crate::db::connection::MongoDB;
+use crate::db::store::{from_documents, BucketQuery, HistoryStore, StoreError};
+use crate::helpers::api_error::ApiError;
+use crate::helpers::filter::merge_condition;
 use crate::helpers::query_parser::QueryParser;
-use crate::helpers::time_intervals::interval_to_seconds;
+use crate::helpers::sort::SortKey;
+use crate::helpers::time_intervals::{bucket_start, next_bucket_start, Bucketing};
+use crate::midgard::client::MidgardClient;
+use crate::models::dataset::Dataset;
 use crate::models::depth_history_model::{
-    DepthHistoryInterval, DepthHistoryMeta, DepthHistoryResponse,
-};
-use crate::routes::types::DepthsHistoryMeta;
-use actix_web::web;
-use futures_util::TryStreamExt;
-use mongodb::{
-    bson::{doc, Document},
-    options::AggregateOptions,
+    luvi_increase, price_shift_loss, DepthHistoryInterval, DepthHistoryIntervalMetrics,
+    DepthHistoryMeta,
 };
+use crate::routes::types::{DepthsHistoryMeta, Intervals};
+use crate::services::backfill_service::ingest_pages;
+use crate::services::export_service::RowMapper;
+use futures_util::{future, TryStreamExt};
+use mongodb::bson::{doc, from_document, to_document, Document};
+use tracing::instrument;
 
-pub async fn fetch_depths_history(
-    mongo_db: &web::Data<MongoDB>,
-    pagination_params: QueryParser,
-    interval_str: &str,
-    sort_by: String,
-    order: i32,
+// Whether `impermanentLoss` or `lpReturn` is returned. They are worked out from whole
+// intervals, so only without them is the rollup narrowed to the returned fields.
+pub fn returns_lp_metrics(pagination_params: &QueryParser, sort: &[SortKey]) -> bool {
+    ["impermanentLoss", "lpReturn"]
+        .iter()
+        .any(|field| pagination_params.keeps(field, sort))
+}
+
+// The bucket query behind `fetch_depths_history`, also used for exports
+pub fn depths_query(
+    pagination_params: &QueryParser,
+    pool_name: &str,
+    bucketing: Bucketing,
+    sort: Vec<SortKey>,
     max_depth: Option<f64>,
     min_depth: Option<f64>,
     liquidity_gt: Option<f64>,
-) -> Result<(DepthsHistoryMeta, Vec<DepthHistoryInterval>), String> {
-    let interval_seconds = interval_to_seconds(interval_str);
-    let skip = pagination_params.skip();
+) -> Result<BucketQuery, ApiError> {
     let mut filter = pagination_params.date_filter();
-    let mut sort_doc = doc! {};
-    sort_doc.insert(sort_by.clone(), order);
+    filter.insert("pool", pool_name);
 
+    // Combined with each other and with `filter=` bounds on the same fields
+    let mut conditions = pagination_params.conditions()?;
     if let Some(min_depth) = min_depth {
-        filter.insert("assetDepth", doc! { "$gte": min_depth });
+        merge_condition(&mut conditions, "assetDepth", "$gte", min_depth)?;
     }
 
     if let Some(max_depth) = max_depth {
-        filter.insert("assetDepth", doc! { "$lte": max_depth });
+        merge_condition(&mut conditions, "assetDepth", "$lte", max_depth)?;
     }
 
     if let Some(liquidity_gt) = liquidity_gt {
-        filter.insert("liquidityUnits", doc! { "$gte": liquidity_gt });
+        merge_condition(&mut conditions, "liquidityUnits", "$gte", liquidity_gt)?;
     }
-    let pipeline = vec![
-        doc! { "$match": filter },
-        doc! { "$group": {
-            "_id": {
-                "$toDate": {
-                    "$subtract": ["$startTime", { "$mod": ["$startTime", interval_seconds] }]
-                }
-            },
-            "assetDepth": { "$last": "$assetDepth" },
-            "runeDepth": { "$last": "$runeDepth" },
-            "assetPrice": { "$last": "$assetPrice" },
-            "assetPriceUSD": { "$last": "$assetPriceUSD" },
-            "liquidityUnits": { "$last": "$liquidityUnits" },
-            "membersCount": { "$last": "$membersCount" },
-            "synthUnits": { "$last": "$synthUnits" },
-            "synthSupply": { "$last": "$synthSupply" },
-            "units": { "$last": "$units" },
-            "luvi": { "$last": "$luvi" },
-            "startTime": { "$first": "$startTime" },
-            "endTime": { "$last": "$endTime" }
-        }},
-        doc! { "$project": {
-            "_id": 0,
-            "startTime": 1,
-            "endTime": 1,
-            "assetDepth": 1,
-            "runeDepth": 1,
-            "assetPrice": 1,
-            "assetPriceUSD": 1,
-            "liquidityUnits": 1,
-            "membersCount": 1,
-            "synthUnits": 1,
-            "synthSupply": 1,
-            "units": 1,
-            "luvi": 1
-        }},
-        doc! { "$sort": sort_doc },
-        doc! { "$skip": skip },
-        doc! { "$limit": pagination_params.count },
-    ];
-    let aggregate_options = AggregateOptions::builder().allow_disk_use(true).build();
-    match mongo_db
-        .depths_history
-        .aggregate(pipeline, aggregate_options)
-        .await
-    {
-        Ok(cursor) => {
-            let results: Vec<DepthHistoryInterval> = cursor
-                .try_collect::<Vec<Document>>()
-                .await
-                .unwrap_or_else(|_| Vec::new())
-                .into_iter()
-                .map(|doc| mongodb::bson::from_document(doc).unwrap())
-                .collect();
+    let after = pagination_params.after(&sort)?;
+    let rollup = if returns_lp_metrics(pagination_params, &sort) {
+        DepthHistoryInterval::rollup()
+    } else {
+        // The depth bounds are matched on the buckets too
+        DepthHistoryInterval::rollup()
+            .into_iter()
+            .filter(|rollup| {
+                pagination_params.keeps(rollup.name, &sort) || conditions.contains_key(rollup.name)
+            })
+            .collect()
+    };
+    Ok(BucketQuery {
+        filter,
+        conditions,
+        pools_filter: None,
+        bucketing,
+        rollup,
+        sort,
+        skip: pagination_params.skip(),
+        limit: pagination_params.count,
+        after,
+    })
+}
 
-            if results.is_empty() {
-                return Err("No data found for the given parameters.".to_string());
-            }
+// First and last bucket of the whole requested range, whatever the page, cursor or sort,
+// so the meta and LP metrics of a range are the same on every page. `None` when the range
+// has no depths.
+pub async fn range_edges(
+    store: &dyn HistoryStore,
+    query: &BucketQuery,
+) -> Result<Option<(DepthHistoryInterval, DepthHistoryInterval)>, ApiError> {
+    // Bounds on the buckets can rule out the ones holding the first and last hours, the
+    // edges are then found among all the buckets
+    if !query.conditions.is_empty() {
+        let first = edge_bucket(store, query, query.filter.clone(), 1).await?;
+        let last = edge_bucket(store, query, query.filter.clone(), -1).await?;
+        return Ok(first.zip(last));
+    }
 
-            let start = &results.first().unwrap();
-            let end = &results.last().unwrap();
-            let depths_meta = DepthHistoryMeta {
-                end_asset_depth: end.asset_depth,
-                end_lp_units: end.liquidity_units,
-                end_member_count: end.members_count,
-                end_rune_depth: end.rune_depth,
-                end_synth_units: end.synth_units,
-                end_time: end.end_time,
-                luvi_increase: 0.0,
-                price_shift_loss: 0.0,
-                start_asset_depth: start.asset_depth,
-                start_lp_units: start.liquidity_units,
-                start_member_count: start.members_count,
-                start_rune_depth: start.rune_depth,
-                start_synth_units: start.synth_units,
-                start_time: start.start_time,
-            };
-            let meta = DepthsHistoryMeta {
-                meta: depths_meta,
-                current_page: pagination_params.page,
-                count: results.len() as i64,
-                has_next_page: results.len() as i64 == pagination_params.count,
-            };
+    // Otherwise only the buckets holding the first and last hours are rolled up
+    let (earliest, latest) = future::try_join(
+        store.earliest_start_time(Dataset::Depths, query.filter.clone()),
+        store.latest_start_time(Dataset::Depths, query.filter.clone()),
+    )
+    .await?;
+    let (Some(earliest), Some(latest)) = (earliest, latest) else {
+        return Ok(None);
+    };
+    let first = edge_bucket(store, query, bucket_filter(query, earliest)?, 1).await?;
+    let last = edge_bucket(store, query, bucket_filter(query, latest)?, -1).await?;
+    Ok(first.zip(last))
+}
 
-            Ok((meta, results))
-        }
-        Err(e) => Err(format!("Error fetching data: {}", e)),
-    }
+// `query.filter` narrowed to the hours of the bucket containing `timestamp`
+fn bucket_filter(query: &BucketQuery, timestamp: i64) -> Result<Document, ApiError> {
+    let mut filter = query.filter.clone();
+    let start = bucket_start(&query.bucketing, timestamp);
+    let end = next_bucket_start(&query.bucketing, timestamp);
+    merge_condition(&mut filter, "startTime", "$gte", start as f64)?;
+    merge_condition(&mut filter, "startTime", "$lt", end as f64)?;
+    Ok(filter)
 }
 
-pub async fn update_depths_data(
-    mongo_db: MongoDB,
-    pool_name: String,
-    from: f64,
-    to: f64,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if from >= to {
-        return Err("Invalid time range: 'from' should be less than 'to'".into());
-    }
+// The first bucket over the hours in `filter` in `startTime` order, read without the
+// pagination counts
+async fn edge_bucket(
+    store: &dyn HistoryStore,
+    query: &BucketQuery,
+    filter: Document,
+    order: i32,
+) -> Result<Option<DepthHistoryInterval>, ApiError> {
+    let edge = BucketQuery {
+        filter,
+        rollup: DepthHistoryInterval::rollup(),
+        sort: vec![SortKey::new("startTime", order)],
+        skip: 0,
+        limit: 1,
+        after: None,
+        ..query.clone()
+    };
+    let buckets: Vec<Document> = store
+        .stream_buckets(Dataset::Depths, &edge)
+        .await?
+        .try_collect()
+        .await?;
+    let buckets: Vec<DepthHistoryInterval> = from_documents(buckets)?;
+    Ok(buckets.into_iter().next())
+}
 
-    let count = 400;
+// Adds the LP metrics measured from `start` to each exported bucket, as the JSON response does
+pub fn lp_metrics_rows(start: DepthHistoryInterval) -> RowMapper {
+    Box::new(move |document| {
+        let interval = from_document(document)
+            .map_err(|e| StoreError(format!("Error decoding interval: {}", e)))?;
+        to_document(&DepthHistoryIntervalMetrics::new(&start, interval))
+            .map_err(|e| StoreError(format!("Error serializing interval: {}", e)))
+    })
+}
 
-    let url: String = format!(
-        "https://midgard.ninerealms.com/v2/history/depths/{}?interval=hour&count={}&from={}&to={}",
-        pool_name, count, from, to
-    );
-    println!("Fetching URL: {}", &url);
-    match reqwest::get(&url).await {
-        Ok(response) => match response.json::<DepthHistoryResponse>().await {
-            Ok(resp) => {
-                let intervals: Vec<DepthHistoryInterval> = resp.intervals;
-                let result = mongo_db
-                    .depths_history
-                    .insert_many(intervals, None)
-                    .await
-                    .map_err(|e| format!("Error Inserting Data into DB: {:?}", e))?;
+#[instrument(skip_all, fields(page = pagination_params.page, count = pagination_params.count))]
+pub async fn fetch_depths_history(
+    store: &dyn HistoryStore,
+    pagination_params: QueryParser,
+    query: BucketQuery,
+) -> Result<(DepthsHistoryMeta, Intervals<DepthHistoryIntervalMetrics>), ApiError> {
+    let page = store.bucketed(Dataset::Depths, &query).await?;
+    let next_cursor = page.next_cursor(&query);
+    let has_next_page = page.has_next_page();
+    let total_pages = page.total_pages(pagination_params.count);
+    let count = page.documents.len() as i64;
 
-                println!(
-                    "Successfully inserted {} intervals from {} to {}",
-                    result.inserted_ids.len(),
-                    from,
-                    to
-                );
-                Ok(())
-            }
-            Err(e) => {
-                println!("Failed to deserialize response: {:?}", e);
-                Err(e.into())
-            }
+    let edges = range_edges(store, &query).await?;
+    let depths_meta = match &edges {
+        Some((start, end)) => DepthHistoryMeta {
+            end_asset_depth: end.asset_depth,
+            end_lp_units: end.liquidity_units,
+            end_member_count: end.members_count,
+            end_rune_depth: end.rune_depth,
+            end_synth_units: end.synth_units,
+            end_time: end.end_time,
+            luvi_increase: luvi_increase(start, end),
+            price_shift_loss: price_shift_loss(start, end),
+            start_asset_depth: start.asset_depth,
+            start_lp_units: start.liquidity_units,
+            start_member_count: start.members_count,
+            start_rune_depth: start.rune_depth,
+            start_synth_units: start.synth_units,
+            start_time: start.start_time,
         },
-        Err(e) => {
-            println!("Failed to fetch data: {:?}", e);
-            Err(e.into())
-        }
+        None => DepthHistoryMeta::default(),
+    };
+    let meta = DepthsHistoryMeta {
+        meta: depths_meta,
+        current_page: pagination_params.page,
+        count,
+        has_next_page,
+        next_cursor,
+        total: page.total,
+        total_pages,
+    };
+
+    if !returns_lp_metrics(&pagination_params, &query.sort) {
+        return Ok((meta, Intervals::Sparse(page.documents)));
     }
+    let results: Vec<DepthHistoryInterval> = from_documents(page.documents)?;
+    let metrics = match edges {
+        Some((start, _)) => results
+            .into_iter()
+            .map(|interval| DepthHistoryIntervalMetrics::new(&start, interval))
+            .collect(),
+        None => Vec::new(),
+    };
+    // The LP metrics need every depth, so buckets are trimmed afterwards
+    let intervals = Intervals::select(metrics, &pagination_params, &query.sort)?;
+    Ok((meta, intervals))
+}
+
+#[instrument(skip(store, midgard))]
+pub async fn update_depths_data(
+    store: &dyn HistoryStore,
+    midgard: &MidgardClient,
+    pool_name: String,
+    from: f64,
+    to: f64,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let scope = doc! { "pool": &pool_name };
+    ingest_pages(store, Dataset::Depths, scope, from, to, |query| {
+        let pool_name = &pool_name;
+        async move { Ok(midgard.depth_history(pool_name, &query).await?.intervals) }
+    })
+    .await
 }
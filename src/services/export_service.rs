@@ -0,0 +1,115 @@
+use actix_web::{
+    error::ErrorInternalServerError,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web::Bytes,
+    HttpResponse,
+};
+use futures_util::{stream, StreamExt};
+use mongodb::bson::Document;
+use std::sync::Arc;
+use tracing::{info_span, Instrument, Span};
+
+use crate::db::store::{BucketQuery, BucketStream, HistoryStore, StoreError};
+use crate::helpers::export::{Column, Encoder, ExportFormat};
+use crate::models::dataset::Dataset;
+
+// Buckets encoded per body chunk while streaming an export
+const EXPORT_CHUNK: usize = 1000;
+
+// Adds what the JSON response works out per interval to each exported bucket
+pub type RowMapper = Box<dyn Fn(Document) -> Result<Document, StoreError> + Send + Sync>;
+
+struct ExportState {
+    store: Arc<dyn HistoryStore>,
+    dataset: Dataset,
+    query: BucketQuery,
+    // Opened with the first chunk, so nothing is read before the body is polled
+    rows: Option<BucketStream>,
+    map_row: Option<RowMapper>,
+    encoder: Option<Encoder>,
+    // The body is streamed after the handler returns, so chunks re-enter the request's span
+    span: Span,
+}
+
+impl ExportState {
+    // Reads the next chunk and encodes it; `None` once the export is complete
+    async fn next_chunk(&mut self) -> Option<Result<Bytes, actix_web::Error>> {
+        self.encoder.as_ref()?;
+        match self.read_chunk().await {
+            Ok(documents) if documents.is_empty() => {
+                let encoder = self.encoder.take()?;
+                Some(encoder.finish().map_err(ErrorInternalServerError))
+            }
+            Ok(documents) => {
+                let encoder = self.encoder.as_mut()?;
+                Some(encoder.encode(documents).map_err(ErrorInternalServerError))
+            }
+            Err(e) => {
+                self.encoder = None;
+                Some(Err(ErrorInternalServerError(e)))
+            }
+        }
+    }
+
+    async fn read_chunk(&mut self) -> Result<Vec<Document>, StoreError> {
+        if self.rows.is_none() {
+            let rows = self.store.stream_buckets(self.dataset, &self.query).await?;
+            self.rows = Some(rows);
+        }
+        let rows = self.rows.as_mut().expect("rows were just opened");
+        let mut documents = Vec::new();
+        while documents.len() < EXPORT_CHUNK {
+            let Some(document) = rows.next().await else {
+                break;
+            };
+            let document = document?;
+            documents.push(match &self.map_row {
+                Some(map_row) => map_row(document)?,
+                None => document,
+            });
+        }
+        Ok(documents)
+    }
+}
+
+// Streams every bucket of `query` in `format` from one store cursor, so exports are not
+// limited to one page. `query.limit` caps the total row count.
+pub fn export_response(
+    store: Arc<dyn HistoryStore>,
+    dataset: Dataset,
+    query: BucketQuery,
+    columns: Vec<Column>,
+    map_row: Option<RowMapper>,
+    format: ExportFormat,
+) -> HttpResponse {
+    let state = ExportState {
+        store,
+        dataset,
+        query,
+        rows: None,
+        map_row,
+        encoder: Some(Encoder::new(format, dataset.name(), columns)),
+        span: info_span!(
+            "export",
+            dataset = dataset.name(),
+            format = format.extension()
+        ),
+    };
+    let body = stream::unfold(state, |mut state| async move {
+        let span = state.span.clone();
+        let chunk = state.next_chunk().instrument(span).await?;
+        Some((chunk, state))
+    });
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "{}.{}",
+                dataset.name(),
+                format.extension()
+            ))],
+        })
+        .streaming(body)
+}
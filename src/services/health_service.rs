@@ -0,0 +1,53 @@
+use crate::db::store::HistoryStore;
+use crate::models::dataset::Dataset;
+use crate::models::health_model::{DatasetFreshness, ReadinessReport, StoreStatus};
+use mongodb::bson::doc;
+use tracing::error;
+
+// Sent in place of store errors, which can carry connection and query details and are
+// only logged
+const UNAVAILABLE: &str = "unavailable";
+
+// Pings the store and checks that the newest interval of every collection started no more
+// than `max_age` seconds before `now`
+pub async fn check_readiness(store: &dyn HistoryStore, now: i64, max_age: i64) -> ReadinessReport {
+    let mongo = match store.ping().await {
+        Ok(()) => StoreStatus {
+            ok: true,
+            error: None,
+        },
+        Err(e) => {
+            error!(error = %e, "Store ping failed");
+            StoreStatus {
+                ok: false,
+                error: Some(UNAVAILABLE.to_string()),
+            }
+        }
+    };
+
+    let mut datasets = Vec::with_capacity(Dataset::ALL.len());
+    for dataset in Dataset::ALL {
+        let (latest_start_time, error) = match store.latest_start_time(dataset, doc! {}).await {
+            Ok(latest) => (latest, None),
+            Err(e) => {
+                error!(%dataset, error = %e, "Error reading the newest interval");
+                (None, Some(UNAVAILABLE.to_string()))
+            }
+        };
+        let age_seconds = latest_start_time.map(|start| now - start);
+        datasets.push(DatasetFreshness {
+            dataset: dataset.name().to_string(),
+            latest_start_time,
+            age_seconds,
+            fresh: age_seconds.is_some_and(|age| age <= max_age),
+            error,
+        });
+    }
+
+    ReadinessReport {
+        ready: mongo.ok && datasets.iter().all(|dataset| dataset.fresh),
+        max_age_seconds: max_age,
+        mongo,
+        datasets,
+    }
+}
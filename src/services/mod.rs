@@ -1,4 +1,8 @@
+pub mod backfill_service;
+pub mod candles_service;
 pub mod depths_service;
 pub mod earnings_service;
+pub mod export_service;
+pub mod health_service;
 pub mod rpmuh_service;
 pub mod swaps_service;
@@ -1,152 +1,113 @@
-use futures_util::TryStreamExt;
-use mongodb::bson::{doc, Document};
-use mongodb::options::AggregateOptions;
-
-use crate::db::connection::MongoDB;
+use crate::db::store::{from_documents, BucketQuery, HistoryStore};
+use crate::helpers::api_error::ApiError;
 use crate::helpers::query_parser::QueryParser;
-use crate::helpers::time_intervals::interval_to_seconds;
-use crate::models::rptmuh_model::{RpmuHistoryInterval, RpmuHistoryResponse};
-use crate::routes::types::RpmuHistoryMeta;
-
-pub async fn fetch_rpmuh_data(
-    mongo_db: &MongoDB,
-    pagination_params: QueryParser,
-    interval_str: &str,
-    sort_by: String,
-    order: i32,
-) -> Result<(RpmuHistoryMeta, Vec<RpmuHistoryInterval>), String> {
-    let skip = pagination_params.skip();
-    let filter = pagination_params.date_filter();
-
-    let mut sort_doc = doc! {};
-    sort_doc.insert(sort_by.clone(), order);
-    let interval_seconds = interval_to_seconds(interval_str);
-    let pipeline = vec![
-        doc! { "$match": filter },
-        doc! { "$sort": sort_doc },
-        doc! {
-            "$group": {
-                "_id": {
-                    "$toDate": {
-                        "$subtract": [
-                            "$startTime",
-                            { "$mod": ["$startTime", interval_seconds] }
-                        ]
-                    }
-                },
-                "count": { "$last": "$count" },
-                "units": { "$last": "$units" },
-                "startTime": { "$first": "$startTime" },
-                "endTime": { "$last": "$endTime" }
-            }
-        },
-        doc! { "$skip": skip },
-        doc! { "$limit": pagination_params.count },
-    ];
-    let aggregate_options = AggregateOptions::builder().allow_disk_use(true).build();
+use crate::helpers::sort::SortKey;
+use crate::helpers::time_intervals::Bucketing;
+use crate::midgard::client::MidgardClient;
+use crate::models::dataset::Dataset;
+use crate::models::rptmuh_model::RpmuHistoryInterval;
+use crate::routes::types::{Intervals, RpmuHistoryMeta};
+use crate::services::backfill_service::ingest_pages;
+use mongodb::bson::doc;
+use tracing::instrument;
 
-    // Fetch the data from MongoDB
-    match mongo_db
-        .members_history
-        .aggregate(pipeline, aggregate_options)
-        .await
-    {
-        Ok(cursor) => {
-            let results: Vec<RpmuHistoryInterval> = cursor
-                .try_collect::<Vec<Document>>()
-                .await
-                .unwrap_or_else(|_| Vec::new())
-                .into_iter()
-                .map(|doc| mongodb::bson::from_document(doc).unwrap())
-                .collect();
+// Fields the meta reads from the first and last bucket, computed whether or not they are
+// returned
+const META_FIELDS: [&str; 4] = ["count", "endTime", "startTime", "units"];
 
-            if results.is_empty() {
-                return Err("No data found for the given parameters.".to_string());
-            }
+// The meta fields are rolled up whatever `fields=` keeps
+pub fn rpmuh_query(
+    pagination_params: &QueryParser,
+    bucketing: Bucketing,
+    sort: Vec<SortKey>,
+) -> Result<BucketQuery, ApiError> {
+    let after = pagination_params.after(&sort)?;
+    let rollup = RpmuHistoryInterval::rollup()
+        .into_iter()
+        .filter(|rollup| {
+            META_FIELDS.contains(&rollup.name) || pagination_params.keeps(rollup.name, &sort)
+        })
+        .collect();
+    Ok(BucketQuery {
+        filter: pagination_params.date_filter(),
+        conditions: pagination_params.conditions()?,
+        pools_filter: None,
+        bucketing,
+        rollup,
+        sort,
+        skip: pagination_params.skip(),
+        limit: pagination_params.count,
+        after,
+    })
+}
 
-            // Calculate the meta values based on the first and last records
-            let start_count = results
-                .first()
-                .map_or("0".to_string(), |r| r.count.to_string());
-            let end_count = results
-                .last()
-                .map_or("0".to_string(), |r| r.count.to_string());
-            let start_units = results
-                .first()
-                .map_or("0".to_string(), |r| r.units.to_string());
-            let end_units = results
-                .last()
-                .map_or("0".to_string(), |r| r.units.to_string());
+#[instrument(skip_all, fields(page = pagination_params.page, count = pagination_params.count))]
+pub async fn fetch_rpmuh_data(
+    store: &dyn HistoryStore,
+    pagination_params: QueryParser,
+    query: BucketQuery,
+) -> Result<(RpmuHistoryMeta, Intervals<RpmuHistoryInterval>), ApiError> {
+    let page = store.bucketed(Dataset::Members, &query).await?;
+    let next_cursor = page.next_cursor(&query);
+    let has_next_page = page.has_next_page();
+    let total_pages = page.total_pages(pagination_params.count);
+    let results: Vec<RpmuHistoryInterval> = from_documents(page.documents)?;
 
-            let start_time = results
-                .first()
-                .map_or("0".to_string(), |r| r.start_time.to_string());
-            let end_time = results
-                .last()
-                .map_or("0".to_string(), |r| r.end_time.to_string());
+    // Calculate the meta values based on the first and last records
+    let start_count = results
+        .first()
+        .map_or("0".to_string(), |r| r.count.to_string());
+    let end_count = results
+        .last()
+        .map_or("0".to_string(), |r| r.count.to_string());
+    let start_units = results
+        .first()
+        .map_or("0".to_string(), |r| r.units.to_string());
+    let end_units = results
+        .last()
+        .map_or("0".to_string(), |r| r.units.to_string());
 
-            let has_next_page = results.len() as i64 == pagination_params.count;
+    let start_time = results
+        .first()
+        .map_or("0".to_string(), |r| r.start_time.to_string());
+    let end_time = results
+        .last()
+        .map_or("0".to_string(), |r| r.end_time.to_string());
 
-            let meta = RpmuHistoryMeta {
-                end_count,
-                end_time,
-                end_units,
-                start_count,
-                start_time,
-                start_units,
-                current_page: pagination_params.page,
-                count: results.len() as i64,
-                has_next_page,
-            };
+    let meta = RpmuHistoryMeta {
+        end_count,
+        end_time,
+        end_units,
+        start_count,
+        start_time,
+        start_units,
+        current_page: pagination_params.page,
+        count: results.len() as i64,
+        has_next_page,
+        next_cursor,
+        total: page.total,
+        total_pages,
+    };
 
-            Ok((meta, results))
-        }
-        Err(e) => Err(format!("Error fetching data: {}", e)),
-    }
+    // The meta fields are dropped again unless they were asked for
+    let intervals = Intervals::select(results, &pagination_params, &query.sort)?;
+    Ok((meta, intervals))
 }
 
+#[instrument(skip(store, midgard))]
 pub async fn update_rpmuh_data(
-    mongo_db: MongoDB,
+    store: &dyn HistoryStore,
+    midgard: &MidgardClient,
     from: f64,
     to: f64,
-) -> Result<(), Box<dyn std::error::Error>> {
-    if from >= to {
-        return Err("Invalid time range: 'from' should be less than 'to'".into());
-    }
-
-    let count = 1;
-    let url: String = format!(
-        "https://midgard.ninerealms.com/v2/history/runepool?interval=hour&count={}&from={}&to={}",
-        count, from, to
-    );
-    println!("Fetching URL: {}", &url);
-
-    match reqwest::get(&url).await {
-        Ok(response) => match response.json::<RpmuHistoryResponse>().await {
-            Ok(resp) => {
-                let intervals: Vec<RpmuHistoryInterval> = resp.intervals;
-                let result = mongo_db
-                    .members_history
-                    .insert_many(intervals, None)
-                    .await
-                    .map_err(|e| format!("Error Inserting Data into DB: {:?}", e))?;
-
-                println!(
-                    "Successfully inserted {} intervals from {} to {}",
-                    result.inserted_ids.len(),
-                    from,
-                    to
-                );
-                Ok(())
-            }
-            Err(e) => {
-                println!("Failed to deserialize response: {:?}", e);
-                Err(e.into())
-            }
-        },
-        Err(e) => {
-            println!("Failed to fetch data: {:?}", e);
-            Err(e.into())
-        }
-    }
+) -> Result<u64, Box<dyn std::error::Error>> {
+    ingest_pages(
+        store,
+        Dataset::Members,
+        doc! {},
+        from,
+        to,
+        |query| async move { Ok(midgard.runepool_history(&query).await?.intervals) },
+    )
+    .await
 }
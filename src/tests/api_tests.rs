@@ -1,13 +1,34 @@
-use crate::{db::connection::MongoDB, routes};
-use actix_web::{http::StatusCode, test, web, App};
+use crate::{
+    config::{ApiConfig, HealthConfig},
+    db::store::HistoryStore,
+    helpers::{
+        gaps::align_to_hour,
+        logging::{trace_requests, REQUEST_ID_HEADER},
+        metrics::{count_ingested, track_requests},
+    },
+    models::{dataset::Dataset, swap_history_model::SwapHistoryInterval, sync_job_model::SyncJob},
+    routes,
+    tests::fixtures::{seeded_store, FIXTURE_HOURS, FIXTURE_START},
+};
+use actix_web::{http::StatusCode, middleware::from_fn, test, web, App};
+use chrono::Utc;
+use mongodb::bson::doc;
+use serde_json::Value;
+use std::sync::Arc;
+
+async fn history_store() -> web::Data<dyn HistoryStore> {
+    let store: Arc<dyn HistoryStore> = seeded_store().await;
+    web::Data::from(store)
+}
 
 #[actix_web::test]
 async fn test_get_runepool_history() {
-    let mongo_db = MongoDB::init().await.expect("Failed to initialize MongoDB");
+    let store = history_store().await;
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(mongo_db))
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
             .configure(routes::rpmuh_history::init),
     )
     .await;
@@ -22,11 +43,12 @@ async fn test_get_runepool_history() {
 
 #[actix_web::test]
 async fn test_get_runepool_history_invalid_sort() {
-    let mongo_db = MongoDB::init().await.expect("Failed to initialize MongoDB");
+    let store = history_store().await;
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(mongo_db))
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
             .configure(routes::rpmuh_history::init),
     )
     .await;
@@ -41,11 +63,12 @@ async fn test_get_runepool_history_invalid_sort() {
 
 #[actix_web::test]
 async fn test_get_earnings_history() {
-    let mongo_db = MongoDB::init().await.expect("Failed to initialize MongoDB");
+    let store = history_store().await;
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(mongo_db))
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
             .configure(routes::earnings_history::init),
     )
     .await;
@@ -60,11 +83,12 @@ async fn test_get_earnings_history() {
 
 #[actix_web::test]
 async fn test_get_earnings_history_invalid_sort() {
-    let mongo_db = MongoDB::init().await.expect("Failed to initialize MongoDB");
+    let store = history_store().await;
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(mongo_db))
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
             .configure(routes::earnings_history::init),
     )
     .await;
@@ -80,11 +104,12 @@ async fn test_get_earnings_history_invalid_sort() {
 // Tests for /swaps
 #[actix_web::test]
 async fn test_get_swaps_history() {
-    let mongo_db = MongoDB::init().await.expect("Failed to initialize MongoDB");
+    let store = history_store().await;
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(mongo_db))
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
             .configure(routes::swaps_history::init),
     )
     .await;
@@ -99,11 +124,12 @@ async fn test_get_swaps_history() {
 
 #[actix_web::test]
 async fn test_get_swaps_history_invalid_sort() {
-    let mongo_db = MongoDB::init().await.expect("Failed to initialize MongoDB");
+    let store = history_store().await;
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(mongo_db))
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
             .configure(routes::swaps_history::init),
     )
     .await;
@@ -119,11 +145,12 @@ async fn test_get_swaps_history_invalid_sort() {
 // Tests for /depth
 #[actix_web::test]
 async fn test_get_depth_data() {
-    let mongo_db = MongoDB::init().await.expect("Failed to initialize MongoDB");
+    let store = history_store().await;
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(mongo_db))
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
             .configure(routes::depths_history::init),
     )
     .await;
@@ -138,11 +165,12 @@ async fn test_get_depth_data() {
 
 #[actix_web::test]
 async fn test_get_depth_data_invalid_sort() {
-    let mongo_db = MongoDB::init().await.expect("Failed to initialize MongoDB");
+    let store = history_store().await;
 
     let app = test::init_service(
         App::new()
-            .app_data(web::Data::new(mongo_db))
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
             .configure(routes::depths_history::init),
     )
     .await;
@@ -154,3 +182,943 @@ async fn test_get_depth_data_invalid_sort() {
     let resp = test::call_service(&app, req).await;
     assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
 }
+
+#[actix_web::test]
+async fn test_get_depth_data_for_pool() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::depths_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/depths?pool=ETH.ETH&interval=day&order=asc&from=2023-10-23T00:00:00&to=2023-10-26T00:00:00")
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    let intervals = body["intervals"].as_array().unwrap();
+    assert_eq!(intervals.len(), 3);
+    assert!(intervals
+        .iter()
+        .all(|interval| interval["pool"] == "ETH.ETH"));
+    // A day bucket starts at its first hour and ends with its last
+    assert_eq!(intervals[0]["startTime"], 1698019200.0);
+    assert_eq!(intervals[0]["endTime"], 1698105600.0);
+}
+
+#[actix_web::test]
+async fn test_get_depth_data_lp_metrics() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::depths_history::init),
+    )
+    .await;
+
+    // Sorted newest first, the meta still runs from the earliest interval to the latest
+    let req = test::TestRequest::get()
+        .uri("/depths?interval=day&from=2023-10-23T00:00:00&to=2023-10-26T00:00:00")
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    let meta = &body["meta"];
+    assert_eq!(meta["startTime"], 1698019200.0);
+    // Day buckets report their last hour: luvi 1023 on the first day and 1071 on the third
+    let luvi_increase = meta["luviIncrease"].as_f64().unwrap();
+    assert!((luvi_increase - 1071.0 / 1023.0).abs() < 1e-9);
+    // Asset and rune depths are equal in every fixture hour, so the price never moves
+    assert_eq!(meta["priceShiftLoss"], 1.0);
+
+    let intervals = body["intervals"].as_array().unwrap();
+    assert_eq!(intervals[0]["impermanentLoss"], 0.0);
+    let lp_return = intervals[0]["lpReturn"].as_f64().unwrap();
+    assert!((lp_return - (1071.0 / 1023.0 - 1.0)).abs() < 1e-9);
+    assert_eq!(intervals[2]["lpReturn"], 0.0);
+}
+
+#[actix_web::test]
+async fn test_depths_meta_skips_buckets_outside_the_depth_bounds() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::depths_history::init),
+    )
+    .await;
+
+    // The first day ends on an asset depth of 1023, so the range starts on the second one
+    let req = test::TestRequest::get()
+        .uri("/depths?interval=day&min_depth=1040&from=2023-10-23T00:00:00&to=2023-10-26T00:00:00")
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    let meta = &body["meta"];
+    assert_eq!(meta["startTime"], (FIXTURE_START + 24 * 3600) as f64);
+    assert_eq!(meta["startAssetDepth"], 1047.0);
+    let luvi_increase = meta["luviIncrease"].as_f64().unwrap();
+    assert!((luvi_increase - 1071.0 / 1047.0).abs() < 1e-9);
+    assert_eq!(body["intervals"].as_array().unwrap().len(), 2);
+}
+
+#[actix_web::test]
+async fn test_depths_meta_is_the_same_on_every_page() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::depths_history::init),
+    )
+    .await;
+
+    let range = "from=2023-10-23T00:00:00&to=2023-10-24T00:00:00&count=5";
+    let mut pages = Vec::new();
+    for page in 1..=2 {
+        let req = test::TestRequest::get()
+            .uri(&format!("/depths?{}&page={}", range, page))
+            .to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        pages.push(body);
+    }
+    let (first, second) = (&pages[0], &pages[1]);
+    for field in [
+        "startTime",
+        "endTime",
+        "startAssetDepth",
+        "endAssetDepth",
+        "luviIncrease",
+        "priceShiftLoss",
+    ] {
+        assert_eq!(first["meta"][field], second["meta"][field], "{}", field);
+    }
+    // The range runs over the whole day, not over the hours of one page
+    assert_eq!(second["meta"]["startTime"], FIXTURE_START as f64);
+    assert_eq!(
+        second["meta"]["endTime"],
+        (FIXTURE_START + 24 * 3600) as f64
+    );
+    let luvi_increase = second["meta"]["luviIncrease"].as_f64().unwrap();
+    assert!((luvi_increase - 1023.0 / 1000.0).abs() < 1e-9);
+
+    // Newest first, so the first interval of page 2 is the 6th hour from the end, luvi 1018
+    let interval = &second["intervals"][0];
+    assert_eq!(interval["luvi"], 1018.0);
+    let lp_return = interval["lpReturn"].as_f64().unwrap();
+    assert!((lp_return - (1018.0 / 1000.0 - 1.0)).abs() < 1e-9);
+}
+
+#[actix_web::test]
+async fn test_get_swaps_history_pages() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::swaps_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/swaps?sort_by=startTime&order=asc&count=3&page=2")
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    let start_times: Vec<i64> = body["intervals"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|interval| interval["startTime"].as_i64().unwrap())
+        .collect();
+    assert_eq!(start_times, vec![1698030000, 1698033600, 1698037200]);
+}
+
+#[actix_web::test]
+async fn test_get_swaps_history_day_totals() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::swaps_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/swaps?interval=day&sort_by=startTime&order=asc&count=1&from=2023-10-23T00:00:00&to=2023-10-24T00:00:00")
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    let day = &body["intervals"][0];
+    // Every field of hour `h` holds `h + 1`
+    assert_eq!(day["totalVolume"], 300.0);
+    assert_eq!(day["totalCount"], 300.0);
+    assert_eq!(day["runePriceUSD"], 24.0);
+    let slip = day["averageSlip"].as_f64().unwrap();
+    assert!((slip - 4900.0 / 300.0).abs() < 1e-9);
+}
+
+#[actix_web::test]
+async fn test_get_earnings_history_day_totals() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::earnings_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/earnings?interval=day&sort_by=startTime&order=asc&from=2023-10-23T00:00:00&to=2023-10-24T00:00:00")
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    let day = &body["intervals"][0];
+    assert_eq!(day["earnings"], 516.0);
+    assert_eq!(day["avgNodeCount"], 21.5);
+    let pools = day["pools"].as_array().unwrap();
+    assert_eq!(pools.len(), 2);
+    assert_eq!(pools[0]["pool"], "BTC.BTC");
+    assert_eq!(pools[0]["earnings"], 300.0);
+    assert_eq!(pools[1]["earnings"], 600.0);
+}
+
+#[actix_web::test]
+async fn test_get_runepool_history_weekly_buckets() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::rpmuh_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/history/runepool?interval=week&order=asc&from=2023-10-23T00:00:00&to=2023-11-06T00:00:00")
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    let start_times: Vec<f64> = body["intervals"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|interval| interval["startTime"].as_f64().unwrap())
+        .collect();
+    // Both weeks start on a Monday
+    assert_eq!(start_times, vec![1698019200.0, 1698624000.0]);
+}
+
+#[actix_web::test]
+async fn test_get_runepool_history_invalid_interval() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::rpmuh_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/history/runepool?interval=fortnight")
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_invalid_parameter_error_body() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::depths_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/depths?count=0").to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "invalid_parameter");
+    assert_eq!(body["field"], "count");
+    assert!(body["message"].as_str().is_some());
+}
+
+#[actix_web::test]
+async fn test_get_depth_data_without_matches_is_empty() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::depths_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/depths?pool=DOGE.DOGE")
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["intervals"].as_array().unwrap().len(), 0);
+    assert_eq!(body["meta"]["count"], 0);
+}
+
+#[actix_web::test]
+async fn test_openapi_spec_lists_model_fields() {
+    let app = test::init_service(App::new().configure(routes::openapi::init)).await;
+
+    let req = test::TestRequest::get().uri("/openapi.json").to_request();
+    let spec: Value = test::call_and_read_body_json(&app, req).await;
+
+    let parameters = spec["paths"]["/swaps"]["get"]["parameters"]
+        .as_array()
+        .unwrap();
+    let sort_by = parameters
+        .iter()
+        .find(|parameter| parameter["name"] == "sort_by")
+        .unwrap();
+    let allowed: Vec<&str> = sort_by["schema"]["enum"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|value| value.as_str().unwrap())
+        .collect();
+    assert_eq!(allowed, SwapHistoryInterval::field_names());
+    assert!(parameters
+        .iter()
+        .any(|parameter| parameter["name"] == "from"));
+    assert!(spec["components"]["schemas"]["ApiError"].is_object());
+
+    let req = test::TestRequest::get().uri("/docs").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn test_get_swaps_history_with_cursor() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::swaps_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/swaps?sort_by=totalFees&count=3")
+        .to_request();
+    let first: Value = test::call_and_read_body_json(&app, req).await;
+    let cursor = first["meta"]["nextCursor"].as_str().unwrap();
+
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/swaps?sort_by=totalFees&count=3&cursor={}",
+            cursor
+        ))
+        .to_request();
+    let second: Value = test::call_and_read_body_json(&app, req).await;
+
+    let req = test::TestRequest::get()
+        .uri("/swaps?sort_by=totalFees&count=3&page=2")
+        .to_request();
+    let paged: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(second["intervals"], paged["intervals"]);
+
+    // A cursor can't be reused with another sort
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/swaps?sort_by=totalCount&count=3&cursor={}",
+            cursor
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_get_swaps_history_totals_on_full_last_page() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::swaps_history::init),
+    )
+    .await;
+
+    // Six day buckets, so the second page of three is full and also the last
+    let req = test::TestRequest::get()
+        .uri("/swaps?interval=day&count=3&page=2&from=2023-10-23T00:00:00&to=2023-10-29T00:00:00")
+        .to_request();
+
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["intervals"].as_array().unwrap().len(), 3);
+    assert_eq!(body["meta"]["total"], 6);
+    assert_eq!(body["meta"]["totalPages"], 2);
+    assert_eq!(body["meta"]["hasNextPage"], false);
+    assert!(body["meta"]["nextCursor"].is_null());
+}
+
+#[actix_web::test]
+async fn test_export_swaps_history_as_csv() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::swaps_history::init),
+    )
+    .await;
+
+    // Exports aren't capped at the 400 buckets of a JSON page
+    let req = test::TestRequest::get()
+        .uri("/swaps?format=csv&order=asc&count=1000")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "text/csv");
+
+    let body = test::read_body(resp).await;
+    let csv = std::str::from_utf8(&body).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next().unwrap(),
+        SwapHistoryInterval::field_names().join(",")
+    );
+    assert_eq!(lines.count(), 14 * 24);
+}
+
+#[actix_web::test]
+async fn test_export_earnings_history_as_ndjson() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::earnings_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/earnings?interval=day&from=2023-10-23T00:00:00&to=2023-10-29T00:00:00")
+        .insert_header(("Accept", "application/x-ndjson"))
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let rows: Vec<Value> = std::str::from_utf8(&body)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(rows.len(), 6);
+    assert!(rows[0]["pools"].is_array());
+}
+
+#[actix_web::test]
+async fn test_export_depths_history_as_parquet() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::depths_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/depths?format=parquet")
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert!(body.starts_with(b"PAR1"));
+    assert!(body.ends_with(b"PAR1"));
+
+    let req = test::TestRequest::get()
+        .uri("/depths?format=xml")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_export_depths_history_with_lp_metrics() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::depths_history::init),
+    )
+    .await;
+
+    // The same rows as the JSON response, LP metrics included
+    let req = test::TestRequest::get()
+        .uri("/depths?format=csv&interval=day&from=2023-10-23T00:00:00&to=2023-10-26T00:00:00&fields=startTime,luvi,impermanentLoss,lpReturn")
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    let csv = std::str::from_utf8(&body).unwrap();
+    let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+    assert_eq!(
+        rows[0],
+        vec!["luvi", "startTime", "impermanentLoss", "lpReturn"]
+    );
+    assert_eq!(rows.len(), 4);
+    // Newest first, as in the JSON response
+    let lp_return: f64 = rows[1][3].parse().unwrap();
+    assert!((lp_return - (1071.0 / 1023.0 - 1.0)).abs() < 1e-9);
+    assert_eq!(rows[3][3], "0.0");
+}
+
+#[actix_web::test]
+async fn test_get_candles_with_volume() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::candles::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/candles?pool=ETH.ETH&interval=day&field=assetPrice&order=asc&from=2023-10-23T00:00:00&to=2023-10-26T00:00:00")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    let candles = body["intervals"].as_array().unwrap();
+    assert_eq!(candles.len(), 3);
+    assert_eq!(body["meta"]["pool"], "ETH.ETH");
+    // ETH.ETH prices are 2000 plus the hour of the fixture
+    assert_eq!(candles[1]["open"], 2024.0);
+    assert_eq!(candles[1]["high"], 2047.0);
+    assert_eq!(candles[1]["low"], 2024.0);
+    assert_eq!(candles[1]["close"], 2047.0);
+    // Swap volume is 1 plus the hour: 24 + (24 + ... + 47)
+    assert_eq!(candles[1]["volume"], 24.0 + 852.0);
+
+    let req = test::TestRequest::get()
+        .uri("/candles?interval=week&field=runePriceUSD&from=2023-10-23T00:00:00&to=2023-10-26T00:00:00")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert!(body["meta"]["pool"].is_null());
+    assert_eq!(body["intervals"][0]["high"], 72.0);
+
+    let req = test::TestRequest::get()
+        .uri("/candles?field=units")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_get_history_with_fields() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::swaps_history::init)
+            .configure(routes::depths_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/swaps?interval=day&sort_by=totalCount&fields=totalVolumeUSD,totalFees")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    let interval = body["intervals"][0].as_object().unwrap();
+    let mut keys: Vec<&str> = interval.keys().map(String::as_str).collect();
+    keys.sort();
+    // `startTime` and the sort field come along for the cursor
+    assert_eq!(
+        keys,
+        ["startTime", "totalCount", "totalFees", "totalVolumeUSD"]
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/depths?fields=lpReturn")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["intervals"][0].as_object().unwrap().len(), 2);
+    assert!(body["meta"]["luviIncrease"].as_f64().unwrap() > 1.0);
+
+    // Without the LP metrics only the returned depths are computed, the meta still has them all
+    let req = test::TestRequest::get()
+        .uri("/depths?fields=runeDepth")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    let interval = body["intervals"][0].as_object().unwrap();
+    let mut keys: Vec<&str> = interval.keys().map(String::as_str).collect();
+    keys.sort();
+    assert_eq!(keys, ["runeDepth", "startTime"]);
+    assert!(body["meta"]["endAssetDepth"].as_f64().unwrap() > 0.0);
+    assert!(body["meta"]["luviIncrease"].as_f64().unwrap() > 1.0);
+
+    let req = test::TestRequest::get()
+        .uri("/swaps?format=csv&fields=totalFees")
+        .to_request();
+    let body = test::call_and_read_body(&app, req).await;
+    assert!(body.starts_with(b"startTime,totalFees\n"));
+
+    let req = test::TestRequest::get()
+        .uri("/swaps?fields=totalFees,pool")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn test_get_history_with_filter() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::swaps_history::init)
+            .configure(routes::depths_history::init),
+    )
+    .await;
+
+    // Swap counts are 1 plus the hour, so ten hours fall in [10, 20)
+    let req = test::TestRequest::get()
+        .uri("/swaps?order=asc&filter=totalCount%3E=10,totalCount%3C20")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["meta"]["total"], 10);
+    assert_eq!(body["intervals"][0]["totalCount"], 10.0);
+
+    // Both depth bounds apply instead of the second replacing the first
+    let req = test::TestRequest::get()
+        .uri("/depths?min_depth=1010&max_depth=1019")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["meta"]["total"], 10);
+
+    let req = test::TestRequest::get()
+        .uri("/depths?min_depth=1010&filter=assetDepth%3C1015")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["meta"]["total"], 5);
+
+    // Conditions apply to the buckets: no hour of the first day has 100 swaps, the day does
+    let req = test::TestRequest::get()
+        .uri("/swaps?interval=day&order=asc&filter=totalCount%3E100")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["meta"]["total"], 14);
+    assert_eq!(body["intervals"][0]["startTime"], FIXTURE_START);
+    assert_eq!(body["intervals"][0]["totalCount"], 300.0);
+
+    // Every excluded value is left out
+    let req = test::TestRequest::get()
+        .uri("/swaps?filter=totalCount%3C5,totalCount!=2,totalCount!=3")
+        .to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["meta"]["total"], 2);
+
+    for filter in ["pool%3E1", "totalCount~5"] {
+        let req = test::TestRequest::get()
+            .uri(&format!("/swaps?filter={}", filter))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[actix_web::test]
+async fn test_get_swaps_history_with_sort_keys() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::swaps_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/swaps?interval=day&sort=-totalFees,startTime&count=3")
+        .to_request();
+    let first: Value = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::get()
+        .uri("/swaps?interval=day&sort_by=totalFees&count=3")
+        .to_request();
+    let legacy: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(first["intervals"], legacy["intervals"]);
+
+    let req = test::TestRequest::get()
+        .uri(&format!(
+            "/swaps?interval=day&sort=-totalFees,startTime&count=3&cursor={}",
+            first["meta"]["nextCursor"].as_str().unwrap()
+        ))
+        .to_request();
+    let second: Value = test::call_and_read_body_json(&app, req).await;
+    let req = test::TestRequest::get()
+        .uri("/swaps?interval=day&sort=-totalFees,startTime&count=3&page=2")
+        .to_request();
+    let paged: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(second["intervals"], paged["intervals"]);
+    assert!(
+        second["intervals"][0]["totalFees"].as_f64() < first["intervals"][2]["totalFees"].as_f64()
+    );
+
+    let req = test::TestRequest::get()
+        .uri("/swaps?sort=-pool")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["field"], "sort");
+}
+
+#[actix_web::test]
+async fn test_metrics_record_requests_by_route() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .wrap(from_fn(track_requests))
+            .configure(routes::depths_history::init)
+            .configure(routes::metrics::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/depths?pool=ETH.ETH&count=2")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let req = test::TestRequest::get().uri("/no-such-route").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    count_ingested(Dataset::Depths, 0);
+
+    let req = test::TestRequest::get().uri("/metrics").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(body.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="/depths",status="200"}"#
+    ));
+    // Unknown paths share one series instead of adding a series per path
+    assert!(body.contains(
+        r#"http_request_duration_seconds_count{method="GET",route="unmatched",status="404"}"#
+    ));
+    assert!(!body.contains("no-such-route"));
+    // Labelled by dataset, whatever its collection is named in the config
+    assert!(body.contains(r#"ingested_documents_total{dataset="depths"}"#));
+}
+
+#[actix_web::test]
+async fn test_healthz_is_always_ok() {
+    let app = test::init_service(App::new().configure(routes::health::init)).await;
+
+    let req = test::TestRequest::get().uri("/healthz").to_request();
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["status"], "ok");
+}
+
+#[actix_web::test]
+async fn test_readyz_reports_stale_collections() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(HealthConfig::default()))
+            .configure(routes::health::init),
+    )
+    .await;
+
+    // The fixtures are from 2023, far past the freshness threshold
+    let req = test::TestRequest::get().uri("/readyz").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["mongo"]["ok"], true);
+    let datasets = body["datasets"].as_array().unwrap();
+    assert_eq!(datasets.len(), 4);
+    assert!(datasets.iter().all(|dataset| dataset["fresh"] == false
+        && dataset["latestStartTime"] == FIXTURE_START + (FIXTURE_HOURS - 1) * 3600));
+}
+
+#[actix_web::test]
+async fn test_readyz_when_every_collection_is_fresh() {
+    let store = seeded_store().await;
+    let last_hour = align_to_hour(Utc::now().timestamp()) - 3600;
+    for dataset in Dataset::ALL {
+        let scope = match dataset {
+            Dataset::Depths => doc! { "pool": "BTC.BTC" },
+            _ => doc! {},
+        };
+        let interval = doc! { "startTime": last_hour as f64, "endTime": (last_hour + 3600) as f64 };
+        store.upsert(dataset, scope, vec![interval]).await.unwrap();
+    }
+    let store: Arc<dyn HistoryStore> = store;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::from(store))
+            .app_data(web::Data::new(HealthConfig::default()))
+            .configure(routes::health::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/readyz").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["ready"], true);
+    assert!(body["datasets"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|dataset| dataset["latestStartTime"] == last_hour));
+}
+
+#[actix_web::test]
+async fn test_request_id_is_returned_or_generated() {
+    let store = history_store().await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(store)
+            .app_data(web::Data::new(ApiConfig::default()))
+            .wrap(from_fn(trace_requests))
+            .configure(routes::swaps_history::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/swaps?count=1")
+        .insert_header((REQUEST_ID_HEADER, "client-42"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "client-42");
+
+    // Errors carry an id too, a new one when the client sent none
+    let req = test::TestRequest::get().uri("/swaps?count=0").to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let id = resp
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(uuid::Uuid::parse_str(id).is_ok());
+}
+
+#[actix_web::test]
+async fn test_admin_jobs_lists_job_state() {
+    let store = seeded_store().await;
+    for (dataset, last_error) in [("swaps", Some("Gaps still missing: 2")), ("depths", None)] {
+        let job = SyncJob {
+            id: dataset.to_string(),
+            dataset: dataset.to_string(),
+            cron: String::from("0 5 * * * *"),
+            timeout_secs: 1800,
+            runs: 3,
+            failures: last_error.map_or(0, |_| 1),
+            last_error: last_error.map(String::from),
+            ..SyncJob::default()
+        };
+        store.save_job(&job).await.unwrap();
+    }
+    let store: Arc<dyn HistoryStore> = store;
+
+    let api_config = ApiConfig {
+        admin_token: Some(String::from("secret")),
+        ..ApiConfig::default()
+    };
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::from(store))
+            .app_data(web::Data::new(api_config))
+            .configure(routes::admin::init),
+    )
+    .await;
+
+    for authorization in [None, Some("Bearer wrong"), Some("secret")] {
+        let mut req = test::TestRequest::get().uri("/admin/jobs");
+        if let Some(authorization) = authorization {
+            req = req.insert_header(("Authorization", authorization));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["code"], "unauthorized");
+    }
+
+    let req = test::TestRequest::get()
+        .uri("/admin/jobs")
+        .insert_header(("Authorization", "Bearer secret"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(resp).await;
+    let jobs = body["jobs"].as_array().unwrap();
+    assert_eq!(jobs.len(), 2);
+    assert_eq!(jobs[0]["dataset"], "depths");
+    assert_eq!(jobs[0]["lastError"], Value::Null);
+    assert_eq!(jobs[1]["dataset"], "swaps");
+    assert_eq!(jobs[1]["lastError"], "Gaps still missing: 2");
+    assert_eq!(jobs[1]["timeoutSecs"], 1800);
+    assert_eq!(jobs[1]["failures"], 1);
+}
+
+#[actix_web::test]
+async fn test_admin_jobs_disabled_without_token() {
+    let store: Arc<dyn HistoryStore> = seeded_store().await;
+    let app = test::init_service(
+        App::new()
+            .app_data(web::Data::from(store))
+            .app_data(web::Data::new(ApiConfig::default()))
+            .configure(routes::admin::init),
+    )
+    .await;
+
+    let req = test::TestRequest::get()
+        .uri("/admin/jobs")
+        .insert_header(("Authorization", "Bearer "))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,100 @@
+use mongodb::bson::{doc, Bson, Document};
+use std::sync::Arc;
+
+use crate::db::{memory::InMemoryStore, store::HistoryStore};
+use crate::models::{
+    dataset::Dataset, depth_history_model::DepthHistoryInterval,
+    earning_history_model::EarningHistoryInterval, rptmuh_model::RpmuHistoryInterval,
+    swap_history_model::SwapHistoryInterval,
+};
+
+// 2023-10-23T00:00:00Z
+pub const FIXTURE_START: i64 = 1698019200;
+pub const FIXTURE_HOURS: i64 = 24 * 14;
+pub const FIXTURE_POOLS: [&str; 2] = ["BTC.BTC", "ETH.ETH"];
+
+// One hourly interval where every numeric field is `base + hour`
+fn interval(fields: Vec<&'static str>, hour: i64, base: f64) -> Document {
+    let start = FIXTURE_START + hour * 3600;
+    let mut document = Document::new();
+    for field in fields {
+        let value = match field {
+            "startTime" => Bson::Double(start as f64),
+            "endTime" => Bson::Double((start + 3600) as f64),
+            "pools" => continue,
+            _ => Bson::Double(base + hour as f64),
+        };
+        document.insert(field, value);
+    }
+    document
+}
+
+fn earnings_pools(hour: i64) -> Vec<Document> {
+    FIXTURE_POOLS
+        .iter()
+        .enumerate()
+        .map(|(index, pool)| {
+            let value = (index as i64 + 1) as f64 * (hour + 1) as f64;
+            doc! {
+                "pool": *pool,
+                "assetLiquidityFees": value,
+                "runeLiquidityFees": value,
+                "totalLiquidityFeesRune": value,
+                "saverEarning": value,
+                "rewards": value,
+                "earnings": value,
+            }
+        })
+        .collect()
+}
+
+// A store holding two weeks of hourly data for every dataset, starting at `FIXTURE_START`
+pub async fn seeded_store() -> Arc<InMemoryStore> {
+    let store = Arc::new(InMemoryStore::new());
+    let hours = 0..FIXTURE_HOURS;
+
+    for (index, pool) in FIXTURE_POOLS.iter().enumerate() {
+        let base = 1000.0 * (index + 1) as f64;
+        let depths = hours
+            .clone()
+            .map(|hour| interval(DepthHistoryInterval::get_feilds(), hour, base))
+            .collect();
+        store
+            .upsert(Dataset::Depths, doc! { "pool": *pool }, depths)
+            .await
+            .unwrap();
+    }
+
+    let earnings = hours
+        .clone()
+        .map(|hour| {
+            let mut document = interval(EarningHistoryInterval::field_names(), hour, 10.0);
+            document.insert("pools", earnings_pools(hour));
+            document
+        })
+        .collect();
+    store
+        .upsert(Dataset::Earnings, doc! {}, earnings)
+        .await
+        .unwrap();
+
+    let members = hours
+        .clone()
+        .map(|hour| interval(RpmuHistoryInterval::field_names(), hour, 100.0))
+        .collect();
+    store
+        .upsert(Dataset::Members, doc! {}, members)
+        .await
+        .unwrap();
+
+    let swaps = hours
+        .map(|hour| {
+            let mut document = interval(SwapHistoryInterval::field_names(), hour, 1.0);
+            document.insert("startTime", FIXTURE_START + hour * 3600);
+            document
+        })
+        .collect();
+    store.upsert(Dataset::Swaps, doc! {}, swaps).await.unwrap();
+
+    store
+}
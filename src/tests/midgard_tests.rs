@@ -0,0 +1,363 @@
+use crate::config::SchedulerConfig;
+use crate::db::{memory::InMemoryStore, store::HistoryStore};
+use crate::helpers::gaps::{align_to_hour, MAX_PAGE_HOURS};
+use crate::helpers::{
+    cron::{start_scheduler, sync_dataset},
+    shutdown::Shutdown,
+};
+use crate::midgard::{
+    client::{HistoryQuery, MidgardClient},
+    error::MidgardError,
+    retry::RetryPolicy,
+};
+use crate::models::{checkpoint_model::SyncCheckpoint, dataset::Dataset};
+use crate::services::backfill_service::{fill_gaps, run_backfill};
+use actix_web::{get, web, App, HttpResponse, HttpServer};
+use chrono::Utc;
+use mongodb::bson::doc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const RUNEPOOL_BODY: &str = r#"{
+    "intervals": [{ "count": 10, "endTime": 1700003600, "startTime": 1700000000, "units": 42 }],
+    "meta": {
+        "endCount": 10, "endTime": 1700003600, "endUnits": 42,
+        "startCount": 10, "startTime": 1700000000, "startUnits": 42
+    }
+}"#;
+
+// Scripted responses the fake Midgard serves, one per request; the last one repeats
+struct FakeMidgard {
+    calls: AtomicUsize,
+    script: Vec<(u16, Option<&'static str>)>,
+}
+
+#[get("/v2/history/runepool")]
+async fn fake_runepool(state: web::Data<FakeMidgard>) -> HttpResponse {
+    let call = state.calls.fetch_add(1, Ordering::SeqCst);
+    let (status, retry_after) = state.script[call.min(state.script.len() - 1)];
+    if status == 200 {
+        return HttpResponse::Ok()
+            .content_type("application/json")
+            .body(RUNEPOOL_BODY);
+    }
+    let mut response = HttpResponse::build(actix_web::http::StatusCode::from_u16(status).unwrap());
+    if let Some(retry_after) = retry_after {
+        response.insert_header(("Retry-After", retry_after));
+    }
+    response.body("fake failure")
+}
+
+#[get("/v2/pools")]
+async fn fake_pools() -> HttpResponse {
+    HttpResponse::Ok().content_type("application/json").body(
+        r#"[
+            { "asset": "BTC.BTC", "status": "available", "assetDepth": "1" },
+            { "asset": "ETH.ETH", "status": "available", "assetDepth": "2" },
+            { "asset": "DOGE.DOGE", "status": "staged", "assetDepth": "3" }
+        ]"#,
+    )
+}
+
+async fn start_fake_midgard(
+    script: Vec<(u16, Option<&'static str>)>,
+) -> (String, Arc<FakeMidgard>) {
+    let state = web::Data::new(FakeMidgard {
+        calls: AtomicUsize::new(0),
+        script,
+    });
+    let shared = state.clone().into_inner();
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .service(fake_runepool)
+            .service(fake_pools)
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .expect("Failed to bind fake Midgard");
+    let addr = server.addrs()[0];
+    actix_web::rt::spawn(server.run());
+    (format!("http://{}", addr), shared)
+}
+
+fn fast_policy() -> RetryPolicy {
+    RetryPolicy {
+        max_retries: 3,
+        base_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(10),
+        min_request_interval: Duration::ZERO,
+    }
+}
+
+fn hourly_query() -> HistoryQuery {
+    HistoryQuery {
+        count: Some(1),
+        from: Some(1700000000),
+        to: Some(1700003600),
+    }
+}
+
+#[actix_web::test]
+async fn test_retries_server_errors_until_success() {
+    let (url, fake) = start_fake_midgard(vec![(500, None), (503, None), (200, None)]).await;
+    let client = MidgardClient::new(&url).with_retry_policy(fast_policy());
+
+    let resp = client.runepool_history(&hourly_query()).await.unwrap();
+    assert_eq!(resp.intervals.len(), 1);
+    assert_eq!(fake.calls.load(Ordering::SeqCst), 3);
+}
+
+#[actix_web::test]
+async fn test_gives_up_after_max_retries() {
+    let (url, fake) = start_fake_midgard(vec![(502, None)]).await;
+    let client = MidgardClient::new(&url).with_retry_policy(fast_policy());
+
+    let err = client
+        .runepool_history(&hourly_query())
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, MidgardError::Status { status, .. } if status.as_u16() == 502));
+    assert_eq!(fake.calls.load(Ordering::SeqCst), 4);
+}
+
+#[actix_web::test]
+async fn test_does_not_retry_client_errors() {
+    let (url, fake) = start_fake_midgard(vec![(400, None), (200, None)]).await;
+    let client = MidgardClient::new(&url).with_retry_policy(fast_policy());
+
+    assert!(client.runepool_history(&hourly_query()).await.is_err());
+    assert_eq!(fake.calls.load(Ordering::SeqCst), 1);
+}
+
+#[actix_web::test]
+async fn test_honours_retry_after_on_429() {
+    let (url, fake) = start_fake_midgard(vec![(429, Some("1")), (200, None)]).await;
+    let policy = RetryPolicy {
+        max_delay: Duration::from_secs(5),
+        ..fast_policy()
+    };
+    let client = MidgardClient::new(&url).with_retry_policy(policy);
+
+    let started = Instant::now();
+    client.runepool_history(&hourly_query()).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert_eq!(fake.calls.load(Ordering::SeqCst), 2);
+}
+
+#[actix_web::test]
+async fn test_caps_retry_after_at_max_delay() {
+    let (url, fake) = start_fake_midgard(vec![(429, Some("86400")), (200, None)]).await;
+    let client = MidgardClient::new(&url).with_retry_policy(fast_policy());
+
+    let started = Instant::now();
+    client.runepool_history(&hourly_query()).await.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(fake.calls.load(Ordering::SeqCst), 2);
+}
+
+#[actix_web::test]
+async fn test_shutdown_cuts_retry_delay_short() {
+    let (url, fake) = start_fake_midgard(vec![(429, Some("60")), (200, None)]).await;
+    let policy = RetryPolicy {
+        max_delay: Duration::from_secs(60),
+        ..fast_policy()
+    };
+    let (trigger, shutdown) = Shutdown::new();
+    let client = MidgardClient::new(&url)
+        .with_retry_policy(policy)
+        .with_shutdown(shutdown);
+
+    let started = Instant::now();
+    actix_web::rt::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        trigger.trigger();
+    });
+    let err = client
+        .runepool_history(&hourly_query())
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, MidgardError::Cancelled { .. }));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(fake.calls.load(Ordering::SeqCst), 1);
+}
+
+#[actix_web::test]
+async fn test_spaces_requests_to_the_same_host() {
+    let (url, _fake) = start_fake_midgard(vec![(200, None)]).await;
+    let policy = RetryPolicy {
+        min_request_interval: Duration::from_millis(200),
+        ..fast_policy()
+    };
+    let client = MidgardClient::new(&url).with_retry_policy(policy);
+
+    let started = Instant::now();
+    for _ in 0..3 {
+        client.runepool_history(&hourly_query()).await.unwrap();
+    }
+    assert!(started.elapsed() >= Duration::from_millis(400));
+}
+
+#[actix_web::test]
+async fn test_discovers_only_available_pools() {
+    let (url, _fake) = start_fake_midgard(vec![(200, None)]).await;
+    let client = MidgardClient::new(&url).with_retry_policy(fast_policy());
+
+    let pools: Vec<String> = client
+        .available_pools()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|pool| pool.asset)
+        .collect();
+    assert_eq!(pools, vec!["BTC.BTC", "ETH.ETH"]);
+}
+
+#[actix_web::test]
+async fn test_ingestion_stops_once_shutdown_is_requested() {
+    let (url, fake) = start_fake_midgard(vec![(200, None)]).await;
+    let client = MidgardClient::new(&url).with_retry_policy(fast_policy());
+    let store = InMemoryStore::new();
+    let (trigger, shutdown) = Shutdown::new();
+    trigger.trigger();
+
+    let report = fill_gaps(
+        &store,
+        &client,
+        Dataset::Members,
+        None,
+        1700000000,
+        1700003600,
+        &shutdown,
+    )
+    .await
+    .unwrap();
+    assert_eq!(report.gaps.len(), 1);
+    assert!(report.filled.is_empty() && report.failed.is_empty());
+
+    let inserted = run_backfill(
+        &store,
+        &client,
+        Dataset::Members,
+        None,
+        1700000000,
+        Some(1700003600),
+        &shutdown,
+    )
+    .await
+    .unwrap();
+    assert_eq!(inserted, 0);
+    assert_eq!(fake.calls.load(Ordering::SeqCst), 0);
+}
+
+#[actix_web::test]
+async fn test_scheduled_job_records_failed_runs() {
+    // A client error fails the run straight away, leaving the gap it was filling
+    let (url, _fake) = start_fake_midgard(vec![(404, None)]).await;
+    let client = MidgardClient::new(&url).with_retry_policy(fast_policy());
+    let store: Arc<dyn HistoryStore> = Arc::new(InMemoryStore::new());
+    let (trigger, shutdown) = Shutdown::new();
+
+    let mut config = SchedulerConfig {
+        lookback_hours: 1,
+        ..SchedulerConfig::default()
+    };
+    config.jobs.depths.enabled = false;
+    config.jobs.earnings.enabled = false;
+    config.jobs.swaps.enabled = false;
+    config.jobs.members.cron = String::from("* * * * * *");
+    let scheduler_store = store.clone();
+    let scheduler = tokio::spawn(async move {
+        start_scheduler(scheduler_store, client, config, shutdown)
+            .await
+            .is_ok()
+    });
+
+    let started = Instant::now();
+    let job = loop {
+        let job = store.load_job(Dataset::Members).await.unwrap();
+        if let Some(job) = job.filter(|job| job.runs > 0) {
+            break job;
+        }
+        assert!(started.elapsed() < Duration::from_secs(10), "Job never ran");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+    assert_eq!(job.cron, "* * * * * *");
+    assert!(job.failures >= 1 && job.last_success_at.is_none());
+    assert_eq!(job.last_error.as_deref(), Some("Gaps still missing: 1"));
+    assert!(job.next_run_at.is_some());
+
+    trigger.trigger();
+    assert!(scheduler.await.unwrap());
+    let jobs = store.list_jobs().await.unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert!(!jobs[0].running);
+}
+
+#[actix_web::test]
+async fn test_backfill_without_end_resumes_interrupted_run() {
+    let (url, fake) = start_fake_midgard(vec![(200, None)]).await;
+    let client = MidgardClient::new(&url).with_retry_policy(fast_policy());
+    let store = InMemoryStore::new();
+    let (_trigger, shutdown) = Shutdown::new();
+
+    // A run over two pages, stopped after the first one
+    let from = 1700000000 - MAX_PAGE_HOURS * 3600;
+    let to = 1700000000 + MAX_PAGE_HOURS * 3600;
+    let id = SyncCheckpoint::key(Dataset::Members.name(), None, from);
+    let checkpoint = SyncCheckpoint {
+        id: id.clone(),
+        dataset: Dataset::Members.name().to_string(),
+        pool: None,
+        from,
+        to,
+        cursor: 1700000000,
+        completed: false,
+        updated_at: 0,
+    };
+    store.save_checkpoint(&checkpoint).await.unwrap();
+
+    let inserted = run_backfill(
+        &store,
+        &client,
+        Dataset::Members,
+        None,
+        from,
+        None,
+        &shutdown,
+    )
+    .await
+    .unwrap();
+    assert_eq!(inserted, 1);
+    assert_eq!(fake.calls.load(Ordering::SeqCst), 1);
+    let checkpoint = store.load_checkpoint(&id).await.unwrap().unwrap();
+    assert_eq!((checkpoint.to, checkpoint.cursor), (to, to));
+    assert!(checkpoint.completed);
+}
+
+#[actix_web::test]
+async fn test_sync_fills_gaps_older_than_the_lookback() {
+    let (url, fake) = start_fake_midgard(vec![(200, None)]).await;
+    let client = MidgardClient::new(&url).with_retry_policy(fast_policy());
+    let store = InMemoryStore::new();
+    let (_trigger, shutdown) = Shutdown::new();
+
+    // Thirty days of downtime since the oldest stored interval, with a one hour lookback
+    let start = align_to_hour(Utc::now().timestamp()) - 30 * 24 * 3600;
+    store
+        .upsert(
+            Dataset::Members,
+            doc! {},
+            vec![doc! { "startTime": start as f64, "endTime": (start + 3600) as f64 }],
+        )
+        .await
+        .unwrap();
+
+    let result = sync_dataset(&store, &client, Dataset::Members, 1, &shutdown).await;
+    assert!(result.is_ok());
+    // The 719 missing hours take two pages
+    assert_eq!(fake.calls.load(Ordering::SeqCst), 2);
+}
@@ -1,2 +1,4 @@
 pub mod api_tests;
+pub mod fixtures;
+pub mod midgard_tests;
 pub mod unit_tests;
@@ -1,8 +1,45 @@
 #[cfg(test)]
 mod tests {
-    use mongodb::bson::doc;
+    use chrono_tz::Tz;
+    use mongodb::bson::{doc, Bson, Document};
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use std::time::Duration;
 
-    use crate::{helpers::query_parser::QueryParser, routes::types::CommonQueryParams};
+    use crate::{
+        config::{Config, LogFormat},
+        db::{
+            connection::{bucket_pipeline, count_pipeline},
+            memory::{bucket, merge_pools},
+            store::{BucketQuery, Rollup, StoreError},
+        },
+        helpers::{
+            api_error::ApiError,
+            cursor::Cursor,
+            export::{columns, ColumnType},
+            filter::{merge_condition, Condition},
+            gaps::{hourly_pages, missing_ranges, TimeRange, HOUR},
+            query_parser::QueryParser,
+            shutdown::Shutdown,
+            sort::{parse_sort, SortKey},
+            time_formatter::parse_cli_date,
+            time_intervals::{bucket_start, next_bucket_start, Bucketing, Interval},
+        },
+        midgard::{
+            client::{HistoryQuery, MidgardClient},
+            retry::{retry_after, RetryPolicy},
+        },
+        models::{
+            candle_model::Candle,
+            dataset::Dataset,
+            depth_history_model::{
+                luvi_increase, price_shift_loss, DepthHistoryInterval, DepthHistoryIntervalMetrics,
+            },
+            earning_history_model::EarningHistoryInterval,
+            swap_history_model::SwapHistoryInterval,
+        },
+        routes::types::CommonQueryParams,
+        services::depths_service::depths_query,
+    };
 
     #[test]
     fn test_valid_query() {
@@ -11,8 +48,13 @@ mod tests {
             page: Some("1".to_string()),
             from: Some("2022-04-01T00:00:00".to_string()),
             to: Some("2023-04-01T00:00:00".to_string()),
+            cursor: None,
+            format: None,
+            fields: None,
+            sort: None,
+            filter: None,
         };
-        let parser = QueryParser::new(&query, 400).unwrap();
+        let parser = QueryParser::new(&query, 400, 1648771200).unwrap();
         assert_eq!(parser.count, 10);
         assert_eq!(parser.page, 1);
     }
@@ -24,8 +66,13 @@ mod tests {
             page: Some("1".to_string()),
             from: None,
             to: None,
+            cursor: None,
+            format: None,
+            fields: None,
+            sort: None,
+            filter: None,
         };
-        let result = QueryParser::new(&query, 400);
+        let result = QueryParser::new(&query, 400, 1648771200);
         assert!(result.is_err());
     }
     #[test]
@@ -35,6 +82,9 @@ mod tests {
             count: 10,
             from: 1648771200,
             to: 1670304000,
+            cursor: None,
+            fields: None,
+            filters: Vec::new(),
         };
         let filter = parser.date_filter();
         let expected = doc! {
@@ -43,4 +93,671 @@ mod tests {
         };
         assert_eq!(filter, expected);
     }
+
+    #[test]
+    fn test_midgard_url_uses_configured_base() {
+        let client = MidgardClient::new("http://localhost:8080/");
+        let query = HistoryQuery {
+            count: Some(400),
+            from: Some(1648771200),
+            to: None,
+        };
+        assert_eq!(
+            client.url_for("/v2/history/swaps", &query),
+            "http://localhost:8080/v2/history/swaps?interval=hour&count=400&from=1648771200"
+        );
+    }
+
+    #[test]
+    fn test_backoff_stays_within_cap() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            min_request_interval: Duration::ZERO,
+        };
+        assert!(policy.backoff(0) <= Duration::from_millis(100));
+        assert!(policy.backoff(3) <= Duration::from_millis(800));
+        assert!(policy.backoff(20) <= Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_retry_after_parsing() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_missing_ranges_merges_contiguous_hours() {
+        let from = 1700000000 - 1700000000 % HOUR;
+        let existing = vec![from, from + HOUR, from + 4 * HOUR, from + 6 * HOUR];
+        let gaps = missing_ranges(&existing, from, from + 8 * HOUR);
+        assert_eq!(
+            gaps,
+            vec![
+                TimeRange {
+                    from: from + 2 * HOUR,
+                    to: from + 4 * HOUR
+                },
+                TimeRange {
+                    from: from + 5 * HOUR,
+                    to: from + 6 * HOUR
+                },
+                TimeRange {
+                    from: from + 7 * HOUR,
+                    to: from + 8 * HOUR
+                },
+            ]
+        );
+        assert!(missing_ranges(&existing[..2], from, from + 2 * HOUR).is_empty());
+    }
+
+    #[test]
+    fn test_hourly_pages_split_at_400_hours() {
+        let pages = hourly_pages(0, 1000 * HOUR);
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].hours(), 400);
+        assert_eq!(
+            pages[2],
+            TimeRange {
+                from: 800 * HOUR,
+                to: 1000 * HOUR
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_cli_date() {
+        assert_eq!(parse_cli_date("2022-04-01"), Ok(1648771200));
+        assert_eq!(parse_cli_date("2022-04-01T01:00:00"), Ok(1648774800));
+        assert!(parse_cli_date("01/04/2022").is_err());
+    }
+
+    #[test]
+    fn test_dataset_from_collection_argument() {
+        assert_eq!("swaps".parse::<Dataset>(), Ok(Dataset::Swaps));
+        assert_eq!("depths_history".parse::<Dataset>(), Ok(Dataset::Depths));
+        assert_eq!("runepool".parse::<Dataset>(), Ok(Dataset::Members));
+        assert!("pools".parse::<Dataset>().is_err());
+    }
+
+    #[test]
+    fn test_bucket_pipeline_divides_weighted_averages() {
+        let query = BucketQuery {
+            filter: doc! {},
+            conditions: doc! {},
+            pools_filter: None,
+            bucketing: Bucketing {
+                interval: Interval::Day,
+                timezone: Tz::UTC,
+            },
+            rollup: vec![
+                Rollup::sum("totalVolume"),
+                Rollup::weighted_avg("averageSlip", "totalVolume"),
+            ],
+            sort: vec![SortKey::new("startTime", 1)],
+            skip: 0,
+            limit: 10,
+            after: None,
+        };
+        let pipeline = bucket_pipeline(&query);
+        assert_eq!(
+            pipeline[1].get_document("$project").unwrap(),
+            &doc! { "_id": 0, "startTime": 1, "totalVolume": 1, "averageSlip": 1 }
+        );
+        let group = pipeline[3].get_document("$group").unwrap();
+        assert_eq!(
+            group.get_document("totalVolume").unwrap(),
+            &doc! { "$sum": "$totalVolume" }
+        );
+        assert_eq!(
+            group.get_document("averageSlip__weight").unwrap(),
+            &doc! { "$sum": "$totalVolume" }
+        );
+        assert!(pipeline[4].contains_key("$addFields"));
+        assert_eq!(
+            pipeline[5].get_document("$project").unwrap(),
+            &doc! { "_id": 0, "averageSlip__weight": 0 }
+        );
+    }
+
+    #[test]
+    fn test_bucket_pipeline_matches_conditions_on_buckets() {
+        let query = BucketQuery {
+            filter: doc! { "startTime": { "$gte": 0.0 } },
+            conditions: doc! { "averageSlip": { "$gt": 5.0 } },
+            pools_filter: None,
+            bucketing: Bucketing {
+                interval: Interval::Day,
+                timezone: Tz::UTC,
+            },
+            rollup: vec![
+                Rollup::first("startTime"),
+                Rollup::sum("totalVolume"),
+                Rollup::weighted_avg("averageSlip", "totalVolume"),
+            ],
+            sort: vec![SortKey::new("startTime", 1)],
+            skip: 0,
+            limit: 10,
+            after: None,
+        };
+        let pipeline = bucket_pipeline(&query);
+        assert_eq!(
+            pipeline[0],
+            doc! { "$match": { "startTime": { "$gte": 0.0 } } }
+        );
+        // After the weighted averages are divided, before the buckets are sorted
+        assert!(pipeline[4].contains_key("$addFields"));
+        assert_eq!(
+            pipeline[5],
+            doc! { "$match": { "averageSlip": { "$gt": 5.0 } } }
+        );
+
+        let counts = count_pipeline(&query);
+        assert!(counts[1]
+            .get_document("$project")
+            .unwrap()
+            .contains_key("averageSlip"));
+    }
+
+    #[test]
+    fn test_bucket_pipeline_counts_separately() {
+        let query = BucketQuery {
+            filter: doc! {},
+            conditions: doc! {},
+            pools_filter: None,
+            bucketing: Bucketing {
+                interval: Interval::Hour,
+                timezone: Tz::UTC,
+            },
+            rollup: vec![Rollup::first("startTime"), Rollup::sum("totalVolume")],
+            sort: vec![SortKey::new("startTime", 1)],
+            skip: 20,
+            limit: 5000,
+            after: None,
+        };
+        let pipeline = bucket_pipeline(&query);
+        assert!(pipeline.iter().all(|stage| !stage.contains_key("$facet")));
+        let stages = pipeline.len();
+        assert_eq!(pipeline[stages - 2], doc! { "$skip": 20_i64 });
+        assert_eq!(pipeline[stages - 1], doc! { "$limit": 5000_i64 });
+
+        // The counts only roll up the sort keys
+        let counts = count_pipeline(&query);
+        assert_eq!(
+            counts[1].get_document("$project").unwrap(),
+            &doc! { "_id": 0, "startTime": 1 }
+        );
+        let facet = counts.last().unwrap().get_document("$facet").unwrap();
+        assert_eq!(
+            facet.get_array("remaining").unwrap().last(),
+            Some(&Bson::Document(doc! { "$count": "count" }))
+        );
+    }
+
+    #[test]
+    fn test_export_columns_take_the_model_types() {
+        let swaps = columns::<SwapHistoryInterval>(vec!["startTime", "totalVolume"]);
+        assert_eq!(swaps[0].kind, ColumnType::Int64);
+        assert_eq!(swaps[1].kind, ColumnType::Double);
+
+        let earnings = columns::<EarningHistoryInterval>(vec!["pools"]);
+        assert_eq!(earnings[0].kind, ColumnType::Text);
+
+        // Fields flattened into the LP metrics keep their type
+        let depths = columns::<DepthHistoryIntervalMetrics>(vec!["luvi", "lpReturn"]);
+        assert!(depths
+            .iter()
+            .all(|column| column.kind == ColumnType::Double));
+    }
+
+    #[test]
+    fn test_bucket_pipeline_writes_renamed_rollups() {
+        let query = BucketQuery {
+            filter: doc! {},
+            conditions: doc! {},
+            pools_filter: None,
+            bucketing: Bucketing {
+                interval: Interval::Day,
+                timezone: Tz::UTC,
+            },
+            rollup: Candle::rollup("assetPrice"),
+            sort: vec![SortKey::new("startTime", 1)],
+            skip: 0,
+            limit: 10,
+            after: None,
+        };
+        let pipeline = bucket_pipeline(&query);
+        let group = pipeline[3].get_document("$group").unwrap();
+        assert_eq!(
+            group.get_document("open").unwrap(),
+            &doc! { "$first": "$assetPrice" }
+        );
+        assert_eq!(
+            group.get_document("high").unwrap(),
+            &doc! { "$max": "$assetPrice" }
+        );
+        assert_eq!(
+            group.get_document("low").unwrap(),
+            &doc! { "$min": "$assetPrice" }
+        );
+        assert!(!group.contains_key("assetPrice"));
+    }
+
+    #[test]
+    fn test_bucket_pipeline_sums_pools_per_pool() {
+        let query = BucketQuery {
+            filter: doc! {},
+            conditions: doc! {},
+            pools_filter: None,
+            bucketing: Bucketing {
+                interval: Interval::Year,
+                timezone: Tz::UTC,
+            },
+            rollup: EarningHistoryInterval::rollup(),
+            sort: vec![SortKey::new("startTime", 1)],
+            skip: 0,
+            limit: 10,
+            after: None,
+        };
+        let pipeline = bucket_pipeline(&query);
+        // No stage pushes the hourly `pools` arrays whole
+        let pushed = |stage: &Document| {
+            stage
+                .get_document("$group")
+                .ok()
+                .and_then(|group| group.get_document("pools").ok())
+                .is_some_and(|pools| pools.get("$push") == Some(&Bson::String("$pools".into())))
+        };
+        assert!(!pipeline.iter().any(pushed));
+
+        let by_pool = pipeline[5].get_document("$group").unwrap();
+        assert_eq!(
+            by_pool.get_document("__pool_earnings").unwrap(),
+            &doc! { "$sum": "$pools.earnings" }
+        );
+        assert_eq!(
+            by_pool.get_document("earnings").unwrap(),
+            &doc! { "$sum": "$earnings" }
+        );
+        let regroup = pipeline[7].get_document("$group").unwrap();
+        assert_eq!(regroup.get_str("_id").unwrap(), "$_id.bucket");
+        assert_eq!(
+            regroup.get_document("earnings").unwrap(),
+            &doc! { "$first": "$earnings" }
+        );
+        assert!(!regroup.contains_key("__pool_earnings"));
+    }
+
+    #[test]
+    fn test_merge_pools_sums_per_pool() {
+        let hours = [
+            Bson::Array(vec![
+                doc! { "pool": "BTC.BTC", "earnings": 1.0 }.into(),
+                doc! { "pool": "ETH.ETH", "earnings": 2.0 }.into(),
+            ]),
+            Bson::Array(vec![doc! { "pool": "BTC.BTC", "earnings": 3.0 }.into()]),
+        ];
+        assert_eq!(
+            merge_pools(&hours),
+            vec![
+                Bson::Document(doc! { "pool": "BTC.BTC", "earnings": 4.0 }),
+                Bson::Document(doc! { "pool": "ETH.ETH", "earnings": 2.0 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_calendar_bucket_starts() {
+        let utc = |interval| Bucketing {
+            interval,
+            timezone: Tz::UTC,
+        };
+        // Thursday 2023-11-16T13:45:00Z
+        let timestamp = 1700142300;
+        assert_eq!(bucket_start(&utc(Interval::Hour), timestamp), 1700139600);
+        assert_eq!(bucket_start(&utc(Interval::Day), timestamp), 1700092800);
+        // Monday 2023-11-13
+        assert_eq!(bucket_start(&utc(Interval::Week), timestamp), 1699833600);
+        assert_eq!(bucket_start(&utc(Interval::Month), timestamp), 1698796800);
+        // 2023-10-01
+        assert_eq!(bucket_start(&utc(Interval::Quarter), timestamp), 1696118400);
+        assert_eq!(bucket_start(&utc(Interval::Year), timestamp), 1672531200);
+
+        let tokyo = Bucketing {
+            interval: Interval::Day,
+            timezone: Tz::Asia__Tokyo,
+        };
+        // 2023-11-16T00:00:00+09:00
+        assert_eq!(bucket_start(&tokyo, timestamp), 1700060400);
+    }
+
+    #[test]
+    fn test_next_bucket_starts() {
+        let utc = |interval| Bucketing {
+            interval,
+            timezone: Tz::UTC,
+        };
+        // Thursday 2023-11-16T13:45:00Z
+        let timestamp = 1700142300;
+        assert_eq!(
+            next_bucket_start(&utc(Interval::Hour), timestamp),
+            1700143200
+        );
+        assert_eq!(
+            next_bucket_start(&utc(Interval::Day), timestamp),
+            1700179200
+        );
+        // Monday 2023-11-20
+        assert_eq!(
+            next_bucket_start(&utc(Interval::Week), timestamp),
+            1700438400
+        );
+        assert_eq!(
+            next_bucket_start(&utc(Interval::Month), timestamp),
+            1701388800
+        );
+        // 2024-01-01 starts both the next quarter and the next year
+        assert_eq!(
+            next_bucket_start(&utc(Interval::Quarter), timestamp),
+            1704067200
+        );
+        assert_eq!(
+            next_bucket_start(&utc(Interval::Year), timestamp),
+            1704067200
+        );
+
+        // 2023-10-29 lasts 25 hours in Berlin, the clocks going back at 03:00
+        let berlin = Bucketing {
+            interval: Interval::Day,
+            timezone: Tz::Europe__Berlin,
+        };
+        assert_eq!(next_bucket_start(&berlin, 1698530400), 1698620400);
+    }
+
+    #[test]
+    fn test_unknown_interval_is_rejected() {
+        assert_eq!("quarter".parse::<Interval>(), Ok(Interval::Quarter));
+        assert!("fortnight".parse::<Interval>().is_err());
+        assert!(Bucketing::parse(Some("minute"), None).is_err());
+        assert!(Bucketing::parse(Some("day"), Some("Mars/Olympus")).is_err());
+    }
+
+    fn depth(asset_depth: f64, rune_depth: f64, units: f64, luvi: f64) -> DepthHistoryInterval {
+        DepthHistoryInterval {
+            pool: "BTC.BTC".to_string(),
+            asset_depth,
+            asset_price: rune_depth / asset_depth,
+            asset_price_usd: 0.0,
+            end_time: 0.0,
+            liquidity_units: units,
+            luvi,
+            members_count: 0.0,
+            rune_depth,
+            start_time: 0.0,
+            synth_supply: 0.0,
+            synth_units: 0.0,
+            units,
+        }
+    }
+
+    #[test]
+    fn test_lp_metrics_between_intervals() {
+        let start = depth(100.0, 100.0, 100.0, 1.0);
+        // The asset doubles in price while fees grow the unit value by 10%
+        let end = depth(100.0, 200.0, 100.0, 1.1);
+
+        assert!((luvi_increase(&start, &end) - 1.1).abs() < 1e-9);
+        let loss = 2.0 * 2f64.sqrt() / 3.0;
+        assert!((price_shift_loss(&start, &end) - loss).abs() < 1e-9);
+        assert_eq!(price_shift_loss(&start, &start), 1.0);
+
+        let metrics = DepthHistoryIntervalMetrics::new(&start, end);
+        assert!((metrics.impermanent_loss - (loss - 1.0)).abs() < 1e-9);
+        assert!((metrics.lp_return - (1.1 * loss - 1.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_depths_rollup_keeps_what_the_lp_metrics_need() {
+        let rollup_names = |fields: &str| {
+            let query = CommonQueryParams {
+                count: None,
+                page: None,
+                from: None,
+                to: None,
+                cursor: None,
+                format: None,
+                fields: Some(fields.to_string()),
+                sort: None,
+                filter: None,
+            };
+            let parser = QueryParser::new(&query, 400, 1648771200).unwrap();
+            let bucketing = Bucketing::parse(None, None).unwrap();
+            let sort = vec![SortKey::new("startTime", -1)];
+            let query =
+                depths_query(&parser, "BTC.BTC", bucketing, sort, None, None, None).unwrap();
+            query
+                .rollup
+                .iter()
+                .map(|rollup| rollup.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(rollup_names("runeDepth"), ["startTime", "runeDepth"]);
+        assert_eq!(
+            rollup_names("lpReturn").len(),
+            DepthHistoryInterval::rollup().len()
+        );
+    }
+
+    #[test]
+    fn test_unit_value_falls_back_to_depths() {
+        // √(400 · 100) / 100 = 2
+        assert_eq!(depth(400.0, 100.0, 100.0, 0.0).unit_value(), 2.0);
+        assert_eq!(depth(400.0, 100.0, 0.0, 0.0).unit_value(), 0.0);
+    }
+
+    #[test]
+    fn test_filter_conditions_parse() {
+        let condition: Condition = "totalVolumeUSD>1e6".parse().unwrap();
+        assert_eq!(condition.field, "totalVolumeUSD");
+        assert_eq!(condition.operator, "$gt");
+        assert_eq!(condition.value, 1e6);
+
+        let condition: Condition = " averageSlip <= 20 ".parse().unwrap();
+        assert_eq!(condition.operator, "$lte");
+        assert_eq!(condition.value, 20.0);
+
+        assert!("totalVolume".parse::<Condition>().is_err());
+        assert!(">5".parse::<Condition>().is_err());
+        assert!("totalVolume>lots".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn test_merge_condition_combines_bounds() {
+        let mut filter = doc! { "startTime": { "$gte": 100.0 } };
+        merge_condition(&mut filter, "assetDepth", "$gte", 10.0).unwrap();
+        merge_condition(&mut filter, "assetDepth", "$lte", 20.0).unwrap();
+        merge_condition(&mut filter, "assetDepth", "$gte", 15.0).unwrap();
+        merge_condition(&mut filter, "startTime", "$gte", 50.0).unwrap();
+        assert_eq!(
+            filter,
+            doc! {
+                "startTime": { "$gte": 100.0 },
+                "assetDepth": { "$gte": 15.0, "$lte": 20.0 },
+            }
+        );
+
+        merge_condition(&mut filter, "units", "$eq", 1.0).unwrap();
+        assert!(merge_condition(&mut filter, "units", "$eq", 2.0).is_err());
+
+        merge_condition(&mut filter, "luvi", "$ne", 1.0).unwrap();
+        assert_eq!(filter.get_document("luvi").unwrap(), &doc! { "$ne": 1.0 });
+        merge_condition(&mut filter, "luvi", "$ne", 2.0).unwrap();
+        merge_condition(&mut filter, "luvi", "$ne", 1.0).unwrap();
+        merge_condition(&mut filter, "luvi", "$ne", 3.0).unwrap();
+        assert_eq!(
+            filter.get_document("luvi").unwrap(),
+            &doc! { "$nin": [1.0, 2.0, 3.0] }
+        );
+    }
+
+    #[test]
+    fn test_parse_sort_keys() {
+        let fields = ["startTime", "totalFees", "totalVolumeUSD"];
+        assert_eq!(
+            parse_sort(Some("-totalVolumeUSD,startTime"), None, None, &fields).unwrap(),
+            vec![
+                SortKey::new("totalVolumeUSD", -1),
+                SortKey::new("startTime", 1)
+            ]
+        );
+        // `startTime` breaks ties in the direction of the first key
+        assert_eq!(
+            parse_sort(None, Some("totalFees"), Some("asc"), &fields).unwrap(),
+            vec![SortKey::new("totalFees", 1), SortKey::new("startTime", 1)]
+        );
+        assert_eq!(
+            parse_sort(None, None, None, &fields).unwrap(),
+            vec![SortKey::new("startTime", -1)]
+        );
+        assert!(parse_sort(Some("-units"), None, None, &fields).is_err());
+        assert!(parse_sort(Some("totalFees,-totalFees"), None, None, &fields).is_err());
+        assert!(parse_sort(Some(","), None, None, &fields).is_err());
+    }
+
+    #[test]
+    fn test_buckets_sort_on_every_key() {
+        let hours: Vec<_> = [(1.0, 1.0), (2.0, 5.0), (1.0, 3.0), (2.0, 4.0)]
+            .into_iter()
+            .enumerate()
+            .map(|(hour, (count, fees))| {
+                doc! {
+                    "startTime": (hour as i64 * 3600) as f64,
+                    "totalCount": count,
+                    "totalFees": fees,
+                }
+            })
+            .collect();
+        let mut query = BucketQuery {
+            filter: doc! {},
+            conditions: doc! {},
+            pools_filter: None,
+            bucketing: Bucketing {
+                interval: Interval::Hour,
+                timezone: Tz::UTC,
+            },
+            rollup: vec![
+                Rollup::first("startTime"),
+                Rollup::last("totalCount"),
+                Rollup::last("totalFees"),
+            ],
+            sort: parse_sort(
+                Some("totalCount,-totalFees"),
+                None,
+                None,
+                &["startTime", "totalCount", "totalFees"],
+            )
+            .unwrap(),
+            skip: 0,
+            limit: 10,
+            after: None,
+        };
+        let fees = |documents: &[mongodb::bson::Document]| -> Vec<f64> {
+            documents
+                .iter()
+                .map(|document| document.get_f64("totalFees").unwrap())
+                .collect()
+        };
+
+        let page = bucket(hours.clone(), &query);
+        assert_eq!(fees(&page.documents), [3.0, 1.0, 5.0, 4.0]);
+
+        query.after = Some(Cursor::after(&page.documents[1], &query.sort));
+        let page = bucket(hours, &query);
+        assert_eq!(fees(&page.documents), [5.0, 4.0]);
+    }
+
+    #[test]
+    fn test_config_file_fills_in_defaults() {
+        let config = Config::parse(
+            r#"
+            [database]
+            url = "mongodb://localhost:27017"
+
+            [api.max_count]
+            earnings = 50
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.database.name, "masterdb");
+        assert_eq!(
+            config.database.collections.history(Dataset::Members),
+            "members_history"
+        );
+        assert_eq!(config.api.max_count.earnings, 50);
+        assert_eq!(config.api.max_count.swaps, 400);
+        assert_eq!(config.server.port, 3000);
+        assert!(config.validate().is_ok());
+
+        assert!(Config::parse("[server]\nbind = \"0.0.0.0\"").is_err());
+        // The documented example stays in sync with the schema
+        let example = Config::parse(include_str!("../../config.example.toml")).unwrap();
+        assert!(example.validate().is_ok());
+    }
+
+    #[test]
+    fn test_config_environment_overrides_and_validation() {
+        let env = |key: &str| match key {
+            "DATABASE_URL" => Some(String::from("mongodb://db:27017")),
+            "SERVER_PORT" => Some(String::from("8080")),
+            "SYNC_SWAPS_CRON" => Some(String::from("every hour")),
+            "SYNC_MEMBERS_TIMEOUT_SECS" => Some(String::from("600")),
+            "LOG_FORMAT" => Some(String::from("text")),
+            _ => None,
+        };
+        let config = Config::default().with_overrides(env).unwrap();
+        assert_eq!(config.database.url, "mongodb://db:27017");
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.logging.format, LogFormat::Text);
+        assert_eq!(config.scheduler.jobs.members.timeout_secs, 600);
+        assert_eq!(config.scheduler.jobs.depths.cron, "0 5 * * * *");
+        let error = config.validate().unwrap_err();
+        assert!(error.0.contains("scheduler.jobs.swaps.cron"));
+
+        let bad_port = |key: &str| (key == "SERVER_PORT").then(|| String::from("http"));
+        assert!(Config::default().with_overrides(bad_port).is_err());
+        // Without a database URL the server can't start
+        assert!(Config::default().validate().is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_shutdown_reaches_every_clone() {
+        let (trigger, shutdown) = Shutdown::new();
+        let mut waiting = shutdown.clone();
+        assert!(!shutdown.is_requested());
+
+        let waiter = tokio::spawn(async move { waiting.requested().await });
+        trigger.trigger();
+        waiter.await.unwrap();
+        assert!(shutdown.is_requested());
+    }
+
+    #[test]
+    fn test_store_errors_are_not_sent_to_clients() {
+        let error = ApiError::from(StoreError(String::from(
+            "Kind: Server selection timeout, mongodb://admin:secret@db:27017",
+        )));
+        assert_eq!(error.status.as_u16(), 500);
+        let body = serde_json::to_value(&error).unwrap();
+        assert_eq!(body["code"], "database_error");
+        assert!(body["details"].is_null());
+        assert!(!body.to_string().contains("secret"));
+    }
 }